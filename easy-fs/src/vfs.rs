@@ -49,6 +49,17 @@ impl Inode {
         }
     }
 
+    /// position of disk inode, unique in the file system
+    pub fn id(&self) -> usize {
+        self.block_id * crate::BLOCK_SIZE + self.block_offset
+    }
+
+    /// size of file in bytes
+    pub fn size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read(|disk_inode| disk_inode.size as usize)
+    }
+
     pub fn read<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
         get_block_cache(self.block_id, Arc::clone(&self.block_dev))
             .lock()
@@ -1,3 +1,76 @@
+//! Generate kernel symbol table used by backtrace.
+//!
+//! Makefile builds kernel twice: the first build produces an ELF whose `nm`
+//! output is given by `ORCA_KSYM`, the second build embeds it. Symbol table
+//! lives in `.rodata`, so addresses in `.text` don't move between two builds.
+//!
+//! Layout of table(little endian):
+//! - count: u64
+//! - `count` entries of (addr: u64, name_offset: u32, name_len: u32), sorted by addr
+//! - names, offset is counted from start of table
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
 fn main() {
     println!("Building application...");
+    println!("cargo:rerun-if-env-changed=ORCA_KSYM");
+
+    let mut symbols = Vec::new();
+    if let Ok(path) = env::var("ORCA_KSYM") {
+        println!("cargo:rerun-if-changed={}", path);
+        let nm = fs::read_to_string(&path).expect("can't read kernel symbols");
+        symbols = parse_nm(&nm);
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("ksymtab.bin");
+    fs::write(out, encode(&symbols)).unwrap();
+}
+
+/// keep symbols of `.text` from lines like `0000000080200000 T _entry`
+fn parse_nm(nm: &str) -> Vec<(u64, String)> {
+    let mut symbols: Vec<(u64, String)> = nm
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let ty = parts.next()?;
+            let name = parts.next()?;
+            if ty != "t" && ty != "T" || name.starts_with(".L") {
+                return None;
+            }
+            Some((addr, strip_hash(name).to_string()))
+        })
+        .collect();
+    symbols.sort_by_key(|(addr, _)| *addr);
+    symbols
+}
+
+/// remove hash like `::h0123456789abcdef` of rust legacy mangling
+fn strip_hash(name: &str) -> &str {
+    if let Some(idx) = name.rfind("::h") {
+        let hash = &name[idx + 3..];
+        if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return &name[..idx];
+        }
+    }
+    name
+}
+
+fn encode(symbols: &[(u64, String)]) -> Vec<u8> {
+    let mut table = Vec::new();
+    table.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+
+    let mut name_offset = 8 + symbols.len() * 16;
+    for (addr, name) in symbols {
+        table.extend_from_slice(&addr.to_le_bytes());
+        table.extend_from_slice(&(name_offset as u32).to_le_bytes());
+        table.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        name_offset += name.len();
+    }
+    for (_, name) in symbols {
+        table.extend_from_slice(name.as_bytes());
+    }
+    table
 }
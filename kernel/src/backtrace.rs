@@ -0,0 +1,47 @@
+//! # Backtrace
+//!
+//! Kernel is built with frame pointers, so every frame saves `ra` at `fp - 8`
+//! and fp of caller at `fp - 16`. We walk the chain until fp leaves stacks of kernel.
+
+use crate::config::{ebss, etext, sbss_with_stack, stext};
+use crate::ksym;
+use crate::task::kernel_stack::{kernel_stack_guard_owner, kernel_stack_owner};
+use core::arch::asm;
+
+const MAX_DEPTH: usize = 32;
+
+/// fp must point to boot stack, emergency stack or kernel stack of a process
+fn valid_fp(fp: usize) -> bool {
+    if fp % 8 != 0 || fp < 16 {
+        return false;
+    }
+    let in_boot_stack = (sbss_with_stack as usize + 16..=ebss as usize).contains(&fp);
+    let in_kernel_stack =
+        kernel_stack_owner(fp - 16).is_some() && kernel_stack_guard_owner(fp - 16).is_none();
+    in_boot_stack || in_kernel_stack
+}
+
+#[inline(never)]
+pub fn backtrace() {
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+
+    println!("backtrace:");
+    for depth in 0..MAX_DEPTH {
+        if !valid_fp(fp) {
+            break;
+        }
+        let ra = unsafe { *((fp - 8) as *const usize) };
+        if !(stext as usize..etext as usize).contains(&ra) {
+            break;
+        }
+        if let Some((name, offset)) = ksym::lookup(ra) {
+            println!("  #{:<2} {:#x} {}+{:#x}", depth, ra, name, offset);
+        } else {
+            println!("  #{:<2} {:#x} <unknown>", depth, ra);
+        }
+        fp = unsafe { *((fp - 16) as *const usize) };
+    }
+}
@@ -3,11 +3,39 @@ pub const M: usize = 1024;
 pub const G: usize = 1024 * 1024;
 
 // stack
+// user stack starts with `USER_STACK_SIZE` mapped and grows down on page
+// fault until it reaches `USER_STACK_LIMIT`, a guard gap lies below the limit,
+// so that a large frame jumping over its top is still a stack overflow
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+pub const USER_STACK_LIMIT: usize = 4096 * 64;
+pub const USER_STACK_GUARD: usize = 4096 * 16;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+// initial kernel heap in bss, it takes frames when it runs out
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+// kernel log ring buffer, see `klog`
+pub const LOG_BUF_SIZE: usize = 4096 * 4;
 pub const APP_BASE_ADDR: usize = 0x1_0000;
 pub const APP_SIZE_LIMIT: usize = 0x20000;
+// shared memory segments and file mappings are placed in [base, end), far
+// above user stack, from a random page above base, end is the top of user
+// space, which is lower half of address space but its last root entry
+pub const USER_MMAP_BASE: usize = 0x20_0000_0000;
+#[cfg(not(feature = "sv48"))]
+pub const USER_MMAP_END: usize = 0x3f_c000_0000;
+#[cfg(feature = "sv48")]
+pub const USER_MMAP_END: usize = 0x7f80_0000_0000;
+// user space is mapped at `USER_ALIAS` of kernel space, i.e. its root
+// entries are copied to upper half, while kernel accesses it, see
+// `mm::uaccess`. The last root entry of upper half is kernel's own.
+#[cfg(not(feature = "sv48"))]
+pub const USER_ALIAS: usize = 0xffff_ffc0_0000_0000;
+#[cfg(feature = "sv48")]
+pub const USER_ALIAS: usize = 0xffff_8000_0000_0000;
+// bits of page number randomized by ASLR: PIE load base within 256 MiB,
+// gap below user stack within 16 MiB, mmap base within 4 GiB
+pub const ASLR_LOAD_BITS: usize = 16;
+pub const ASLR_STACK_BITS: usize = 12;
+pub const ASLR_MMAP_BITS: usize = 20;
 
 // qemu clock frequncy: 12.5MHz
 pub const CLOCK_FREQ: usize = 12_500_000;
@@ -16,8 +44,18 @@ pub const CLOCK_FREQ: usize = 12_500_000;
 pub const PAGE_SIZE: usize = 4096;
 pub const PAGE_OFFSET: usize = 12;
 
-// physical memory
-pub const MEMORY_END: usize = 0x8800_0000; // 128M Memory
+// physical memory, its size must match `-m` of qemu, see `MEM` in Makefile
+pub const MEMORY_START: usize = 0x8000_0000;
+pub const MEMORY_END: usize = MEMORY_START + MEMORY_SIZE;
+const MEMORY_SIZE: usize = if cfg!(feature = "mem_1g") {
+    1024 << 20
+} else if cfg!(feature = "mem_512m") {
+    512 << 20
+} else if cfg!(feature = "mem_256m") {
+    256 << 20
+} else {
+    128 << 20
+};
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
@@ -35,8 +73,15 @@ extern "C" {
     pub fn sbss();
     pub fn ebss();
     pub fn strampoline();
+    pub fn sksymtab();
+    pub fn eksymtab();
+    pub fn sex_table();
+    pub fn eex_table();
 }
 
 // MMIO for qemu
 // (start addr, length)
-pub const MMIO: &[(usize, usize)] = &[(0x10001000, 0x1000)];
+pub const MMIO: &[(usize, usize)] = &[
+    (0x0c00_0000, 0x40_0000), // PLIC
+    (0x10001000, 0x1000),     // virtio-blk
+];
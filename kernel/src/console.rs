@@ -1,6 +1,12 @@
 #![allow(unused)]
+use crate::klog;
 use crate::sbi::console_putchar;
+use crate::trap::{disable_kernel_interrupt, restore_kernel_interrupt};
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::RwLock;
 
 pub struct Stdout;
 
@@ -64,6 +70,117 @@ pub fn test_err() {
     println_with_color("Err", RED);
 }
 
+/// # Log level
+/// `error!`, `warn!`, `info!`, `debug!` and `trace!` only print when their level
+/// is enabled, `kernel!` and `test!` always print.
+///
+/// - initial level is chosen by cargo feature `log_xxx`, default: info
+/// - levels above initial level are compiled out in release build
+/// - level can be changed at runtime by `sys_log_level`, for the whole kernel
+///   or for a module, e.g. `mm::memory_set`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    Off = 0,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn from_usize(level: usize) -> Option<Self> {
+        match level {
+            0 => Some(Self::Off),
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            5 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+const FEATURE_LEVEL: LogLevel = if cfg!(feature = "log_trace") {
+    LogLevel::Trace
+} else if cfg!(feature = "log_debug") {
+    LogLevel::Debug
+} else if cfg!(feature = "log_warn") {
+    LogLevel::Warn
+} else if cfg!(feature = "log_error") {
+    LogLevel::Error
+} else {
+    LogLevel::Info
+};
+
+/// debug build keeps every level, so that they can be turned on at runtime
+pub const STATIC_MAX_LEVEL: LogLevel = if cfg!(debug_assertions) {
+    LogLevel::Trace
+} else {
+    FEATURE_LEVEL
+};
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(FEATURE_LEVEL as u8);
+
+lazy_static! {
+    /// (module, level), overrides global level for module and its children
+    static ref LOG_FILTERS: RwLock<Vec<(String, LogLevel)>> = RwLock::new(Vec::new());
+}
+
+/// `module` is given by `module_path!()`, like `orca::mm::memory_set`
+fn in_module(module: &str, filter: &str) -> bool {
+    let module = module.strip_prefix("orca::").unwrap_or(module);
+    match module.strip_prefix(filter) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+pub fn log_enabled(level: LogLevel, module: &str) -> bool {
+    let filters = LOG_FILTERS.read();
+    let max_level = filters
+        .iter()
+        .rev()
+        .find(|(filter, _)| in_module(module, filter))
+        .map_or(LOG_LEVEL.load(Ordering::Relaxed), |(_, level)| *level as u8);
+    level as u8 <= max_level
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// the latest filter wins if more than one filter matches a module
+pub fn set_module_log_level(module: &str, level: LogLevel) {
+    // logging in interrupt handler would spin on LOG_FILTERS forever
+    let sie = disable_kernel_interrupt();
+    let mut filters = LOG_FILTERS.write();
+    filters.retain(|(filter, _)| filter != module);
+    filters.push((String::from(module), level));
+    drop(filters);
+    restore_kernel_interrupt(sie);
+}
+
+/// check static level first, so that disabled levels are compiled out
+#[macro_export]
+macro_rules! log_enabled {
+    ($level: ident) => {
+        $crate::console::LogLevel::$level <= $crate::console::STATIC_MAX_LEVEL
+            && $crate::console::log_enabled($crate::console::LogLevel::$level, module_path!())
+    };
+}
+
+/// print a log line in color and keep it in kernel log buffer
+pub fn log(tag: &str, head_color: &str, body_color: &str, args: fmt::Arguments) {
+    print(format_args!(
+        "{}[{}]\x1b[0m{} {}\x1b[0m\n",
+        head_color, tag, body_color, args
+    ));
+    klog::record(tag, args);
+}
+
 /// color for logo
 /// - error: red
 /// - info: purple
@@ -75,101 +192,81 @@ pub fn test_err() {
 #[macro_export]
 macro_rules! error {
     ($fmt: literal $(, $($arg: tt)+)?) => {
-        $crate::console::print(
-            format_args!(
-                concat!(
-                    "\x1b[31;1m",
-                    "[ERROR]\x1b[0m",
-                    "\x1b[31m ",
-                    $fmt,
-                    "\x1b[0m\n")
-                    $(, $($arg)+)?
-            )
-        );
+        if $crate::log_enabled!(Error) {
+            $crate::console::log(
+                "ERROR",
+                "\x1b[31;1m",
+                "\x1b[31m",
+                format_args!($fmt $(, $($arg)+)?)
+            );
+        }
     };
 }
 
 #[macro_export]
 macro_rules! info {
     ($fmt: literal $(, $($arg: tt)+)?) => {
-        $crate::console::print(
-            format_args!(
-                concat!(
-                    "\x1b[35;1m",
-                    "[INFO]\x1b[0m",
-                    "\x1b[35m ",
-                    $fmt,
-                    "\x1b[0m\n")
-                    $(, $($arg)+)?
-            )
-        );
+        if $crate::log_enabled!(Info) {
+            $crate::console::log(
+                "INFO",
+                "\x1b[35;1m",
+                "\x1b[35m",
+                format_args!($fmt $(, $($arg)+)?)
+            );
+        }
     };
 }
 
 #[macro_export]
 macro_rules! warn {
     ($fmt: literal $(, $($arg: tt)+)?) => {
-        $crate::console::print(
-            format_args!(
-                concat!(
-                    "\x1b[33;1m",
-                    "[WARN]\x1b[0m",
-                    "\x1b[33m ",
-                    $fmt,
-                    "\x1b[0m\n")
-                    $(, $($arg)+)?
-            )
-        );
+        if $crate::log_enabled!(Warn) {
+            $crate::console::log(
+                "WARN",
+                "\x1b[33;1m",
+                "\x1b[33m",
+                format_args!($fmt $(, $($arg)+)?)
+            );
+        }
     };
 }
 
 #[macro_export]
 macro_rules! debug {
     ($fmt: literal $(, $($arg: tt)+)?) => {
-        $crate::console::print(
-            format_args!(
-                concat!(
-                    "\x1b[32;1m",
-                    "[DEBUG]\x1b[0m",
-                    "\x1b[32m ",
-                    $fmt,
-                    "\x1b[0m\n")
-                    $(, $($arg)+)?
-            )
-        );
+        if $crate::log_enabled!(Debug) {
+            $crate::console::log(
+                "DEBUG",
+                "\x1b[32;1m",
+                "\x1b[32m",
+                format_args!($fmt $(, $($arg)+)?)
+            );
+        }
     };
 }
 
 #[macro_export]
 macro_rules! trace {
     ($fmt: literal $(, $($arg: tt)+)?) => {
-        $crate::console::print(
-            format_args!(
-                concat!(
-                    "\x1b[1m",
-                    "[TRACE]\x1b[0m",
-                    "\x1b[2m ",
-                    $fmt,
-                    "\x1b[0m\n")
-                    $(, $($arg)+)?
-            )
-        );
+        if $crate::log_enabled!(Trace) {
+            $crate::console::log(
+                "TRACE",
+                "\x1b[1m",
+                "\x1b[2m",
+                format_args!($fmt $(, $($arg)+)?)
+            );
+        }
     };
 }
 
 #[macro_export]
 macro_rules! kernel {
     ($fmt: literal $(, $($arg: tt)+)?) => {
-        $crate::console::print(
-            format_args!(
-                concat!(
-                    "\x1b[36;1m",
-                    "[KERNEL]\x1b[0m",
-                    "\x1b[36m ",
-                    $fmt,
-                    "\x1b[0m\n")
-                    $(, $($arg)+)?
-            )
+        $crate::console::log(
+            "KERNEL",
+            "\x1b[36;1m",
+            "\x1b[36m",
+            format_args!($fmt $(, $($arg)+)?)
         );
     };
 }
@@ -1,14 +1,9 @@
 use crate::mm::address::PhysAddr;
-use crate::mm::address::PhysPageNum;
-use crate::mm::address::StepByOne;
 use crate::mm::address::VirtAddr;
-use crate::mm::frame_allocator::FrameTracker;
-use crate::mm::frame_allocator::{frame_alloc, frame_dealloc};
+use crate::mm::frame_allocator::{frames_alloc_contiguous, frames_dealloc_contiguous};
 use crate::mm::memory_set::KERNEL_SPACE;
 use crate::mm::page_table::PageTable;
-use crate::mm::page_table::UserBuf;
 
-use alloc::vec::Vec;
 use easy_fs::BlockDevice;
 use spin::Mutex;
 use virtio_drivers::{VirtIOBlk, VirtIOHeader};
@@ -41,34 +36,17 @@ impl BlockDevice for VirtIOBlock {
     }
 }
 
-lazy_static! {
-    static ref VRING: Mutex<Vec<FrameTracker>> = Mutex::new(Vec::new());
-}
-
+/// vring must be physically contiguous
 #[no_mangle]
 pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
-    let mut ppn_base = PhysPageNum(0);
-    for i in 0..pages {
-        let frame = frame_alloc().unwrap();
-        if i == 0 {
-            // first frame is the base of whole vring
-            ppn_base = frame.ppn;
-        }
-        // we need to obtain a linear frame
-        // so when $i, addr of frame is $base + $i
-        assert_eq!(frame.ppn.0, ppn_base.0 + i);
-        VRING.lock().push(frame);
-    }
-    ppn_base.into()
+    frames_alloc_contiguous(pages)
+        .expect("no memory for virtio dma")
+        .into()
 }
 
 #[no_mangle]
 pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
-    let mut ppn_base: PhysPageNum = pa.into();
-    for _ in 0..pages {
-        frame_dealloc(ppn_base);
-        ppn_base.step();
-    }
+    frames_dealloc_contiguous(pa.into(), pages);
     0
 }
 
@@ -0,0 +1,93 @@
+//! # PLIC
+//! Platform-Level Interrupt Controller of qemu virt machine, which routes
+//! external interrupts of devices to harts.
+//!
+//! We only use context of hart0 in supervisor mode. Drivers install handler
+//! of their irq by `register`.
+
+use crate::sync::UniProcSafeCell;
+use crate::trap::interrupt::{register_interrupt_handler, InterruptHandler};
+use core::ptr::{read_volatile, write_volatile};
+use riscv::register::scause::Interrupt;
+
+const PLIC_BASE: usize = 0x0c00_0000;
+/// hart0 supervisor mode
+const PLIC_CONTEXT: usize = 1;
+/// qemu virt has less than 64 interrupt sources
+const PLIC_MAX_IRQ: usize = 64;
+
+fn priority_ptr(irq: usize) -> *mut u32 {
+    (PLIC_BASE + 4 * irq) as *mut u32
+}
+
+fn enable_ptr(irq: usize) -> *mut u32 {
+    (PLIC_BASE + 0x2000 + 0x80 * PLIC_CONTEXT + 4 * (irq / 32)) as *mut u32
+}
+
+fn threshold_ptr() -> *mut u32 {
+    (PLIC_BASE + 0x20_0000 + 0x1000 * PLIC_CONTEXT) as *mut u32
+}
+
+fn claim_ptr() -> *mut u32 {
+    (PLIC_BASE + 0x20_0004 + 0x1000 * PLIC_CONTEXT) as *mut u32
+}
+
+lazy_static! {
+    static ref IRQ_HANDLERS: UniProcSafeCell<[Option<InterruptHandler>; PLIC_MAX_IRQ]> =
+        UniProcSafeCell::new([None; PLIC_MAX_IRQ]);
+}
+
+/// accept interrupts of any priority
+pub fn init() {
+    unsafe {
+        write_volatile(threshold_ptr(), 0);
+    }
+    register_interrupt_handler(Interrupt::SupervisorExternal, external_interrupt_handler);
+}
+
+/// install `handler` for `irq` and let `irq` be delivered to this hart
+pub fn register(irq: usize, handler: InterruptHandler) {
+    assert!(irq > 0 && irq < PLIC_MAX_IRQ, "invalid irq {}", irq);
+    let mut handlers = IRQ_HANDLERS.borrow_mut();
+    assert!(handlers[irq].is_none(), "irq {} has been registered", irq);
+    handlers[irq] = Some(handler);
+    drop(handlers);
+    enable(irq);
+}
+
+fn enable(irq: usize) {
+    unsafe {
+        write_volatile(priority_ptr(irq), 1);
+        let enable = read_volatile(enable_ptr(irq));
+        write_volatile(enable_ptr(irq), enable | 1 << (irq % 32));
+    }
+}
+
+/// get the highest priority pending interrupt
+fn claim() -> Option<usize> {
+    let irq = unsafe { read_volatile(claim_ptr()) } as usize;
+    if irq == 0 {
+        None
+    } else {
+        Some(irq)
+    }
+}
+
+/// tell PLIC `irq` has been handled
+fn complete(irq: usize) {
+    unsafe {
+        write_volatile(claim_ptr(), irq as u32);
+    }
+}
+
+fn external_interrupt_handler() {
+    while let Some(irq) = claim() {
+        let handler = IRQ_HANDLERS.borrow_mut().get(irq).copied().flatten();
+        if let Some(handler) = handler {
+            handler();
+        } else {
+            warn!("unhandled external interrupt, irq = {}", irq);
+        }
+        complete(irq);
+    }
+}
@@ -3,7 +3,7 @@ use alloc::{sync::Arc, vec::Vec};
 use easy_fs::{vfs::Inode, EasyFileSystem};
 use spin::Mutex;
 
-use super::File;
+use super::{page_cache, File};
 
 pub struct OSInode {
     readable: bool,
@@ -30,7 +30,7 @@ impl OSInode {
         let mut buffer = [0u8; 512];
         let mut res = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = page_cache::read_at(&inner.inode, inner.offset, &mut buffer);
             if len == 0 {
                 return res;
             }
@@ -40,6 +40,14 @@ impl OSInode {
 
         res
     }
+
+    /// write `data` at current offset, used by kernel itself
+    pub fn write_all(&self, data: &[u8]) -> usize {
+        let mut inner = self.inner.lock();
+        let size = inner.inode.write_at(inner.offset, data);
+        inner.offset += size;
+        size
+    }
 }
 
 impl File for OSInode {
@@ -47,7 +55,7 @@ impl File for OSInode {
         let mut inner = self.inner.lock();
         let mut total_size = 0;
         for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, slice);
+            let read_size = page_cache::read_at(&inner.inode, inner.offset, slice);
             if read_size == 0 {
                 break;
             }
@@ -62,7 +70,7 @@ impl File for OSInode {
         let mut inner = self.inner.lock();
         let mut total_size = 0;
         for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, slice);
+            let write_size = page_cache::write_at(&inner.inode, inner.offset, slice);
 
             // success write size equals to slice size
             assert_eq!(write_size, slice.len());
@@ -81,6 +89,10 @@ impl File for OSInode {
     fn writeable(&self) -> bool {
         self.writeable
     }
+
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(self.inner.lock().inode.clone())
+    }
 }
 
 lazy_static! {
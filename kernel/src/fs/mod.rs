@@ -1,6 +1,9 @@
 use crate::mm::page_table::UserBuf;
+use alloc::sync::Arc;
+use easy_fs::vfs::Inode;
 
 pub mod inode;
+pub mod page_cache;
 pub mod stdio;
 
 pub trait File: Send + Sync {
@@ -8,4 +11,8 @@ pub trait File: Send + Sync {
     fn write(&self, buf: UserBuf) -> usize;
     fn readable(&self) -> bool;
     fn writeable(&self) -> bool;
+    /// inode behind file, only regular files can be mapped by `mmap`
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
 }
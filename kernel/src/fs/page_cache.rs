@@ -0,0 +1,137 @@
+//! # Page cache
+//!
+//! Pages of files mapped by `mmap`, found by (inode, page index). Every
+//! mapping of the same page shares one frame, so `MAP_SHARED` writers see
+//! each other, and `MAP_PRIVATE` mappings copy it on write.
+//!
+//! The cache only keeps `Weak`, a page is dropped once no area maps it.
+//! Page doesn't know if it's dirty: every mapping tracks pages written
+//! through itself, and writes them back by `FilePage::sync` before it's gone.
+//!
+//! `read` and `write` of file go through `read_at` and `write_at` here, so
+//! they see writes of mappings not synced yet, and mappings see theirs,
+//! instead of writing stale data back over them.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use easy_fs::vfs::Inode;
+use lazy_static::lazy_static;
+
+use crate::{
+    config::PAGE_SIZE,
+    mm::{
+        address::PhysPageNum,
+        frame_allocator::{frame_alloc, FrameTracker},
+        MmError, MmResult,
+    },
+    sync::UniProcSafeCell,
+};
+
+pub struct FilePage {
+    inode: Arc<Inode>,
+    index: usize,
+    frame: FrameTracker,
+}
+
+impl FilePage {
+    pub fn ppn(&self) -> PhysPageNum {
+        self.frame.ppn
+    }
+
+    /// # sync
+    /// write page back to file, bytes beyond end of file are dropped, so
+    /// file never grows by `mmap`
+    pub fn sync(&self) {
+        let offset = self.index * PAGE_SIZE;
+        let size = self.inode.size();
+        if offset < size {
+            let len = (size - offset).min(PAGE_SIZE);
+            self.inode
+                .write_at(offset, &self.frame.ppn.bytes_array()[..len]);
+        }
+    }
+}
+
+lazy_static! {
+    /// (inode id, page index) -> page
+    static ref PAGE_CACHE: UniProcSafeCell<BTreeMap<(usize, usize), Weak<FilePage>>> =
+        UniProcSafeCell::new(BTreeMap::new());
+}
+
+fn cached_page(inode: &Arc<Inode>, index: usize) -> Option<Arc<FilePage>> {
+    PAGE_CACHE
+        .borrow_mut()
+        .get(&(inode.id(), index))
+        .and_then(|p| p.upgrade())
+}
+
+/// call `f(bytes, at)` for each cached page of `inode` in
+/// `[offset, offset + len)`, `bytes` is the part of page in the range,
+/// starting from `at` of it
+fn for_each_cached(
+    inode: &Arc<Inode>,
+    offset: usize,
+    len: usize,
+    mut f: impl FnMut(&mut [u8], usize),
+) {
+    let end = offset + len;
+    for index in offset / PAGE_SIZE..(end + PAGE_SIZE - 1) / PAGE_SIZE {
+        if let Some(page) = cached_page(inode, index) {
+            let page_start = index * PAGE_SIZE;
+            let start = offset.max(page_start);
+            let page_end = end.min(page_start + PAGE_SIZE);
+            let bytes = &mut page.ppn().bytes_array()[start - page_start..page_end - page_start];
+            f(bytes, start - offset);
+        }
+    }
+}
+
+/// # read_at
+/// read file like `Inode::read_at`, cached pages may have writes of
+/// mappings not synced yet
+pub fn read_at(inode: &Arc<Inode>, offset: usize, buf: &mut [u8]) -> usize {
+    let size = inode.read_at(offset, buf);
+    for_each_cached(inode, offset, size, |bytes, at| {
+        buf[at..at + bytes.len()].copy_from_slice(bytes)
+    });
+    size
+}
+
+/// # write_at
+/// write file like `Inode::write_at`, cached pages are updated too
+pub fn write_at(inode: &Arc<Inode>, offset: usize, buf: &[u8]) -> usize {
+    let size = inode.write_at(offset, buf);
+    for_each_cached(inode, offset, size, |bytes, at| {
+        bytes.copy_from_slice(&buf[at..at + bytes.len()])
+    });
+    size
+}
+
+/// # get_page
+/// page `index` of `inode`, read from file if it isn't cached.
+/// Page wholly beyond end of file can't be mapped, like `SIGBUS` of Linux
+pub fn get_page(inode: &Arc<Inode>, index: usize) -> MmResult<Arc<FilePage>> {
+    if let Some(page) = cached_page(inode, index) {
+        return Ok(page);
+    }
+
+    if index * PAGE_SIZE >= inode.size() {
+        return Err(MmError::InvalidArgument);
+    }
+    // frame is zeroed, the part beyond end of file stays zero
+    let frame = frame_alloc().ok_or(MmError::OutOfMemory)?;
+    inode.read_at(index * PAGE_SIZE, frame.ppn.bytes_array());
+    let page = Arc::new(FilePage {
+        inode: inode.clone(),
+        index,
+        frame,
+    });
+
+    let mut cache = PAGE_CACHE.borrow_mut();
+    // forget pages that are gone
+    cache.retain(|_, page| page.strong_count() > 0);
+    cache.insert((inode.id(), index), Arc::downgrade(&page));
+    Ok(page)
+}
@@ -1,7 +1,13 @@
-use crate::{sbi::consolo_getchar, task::suspend_cur_and_run_next};
+use crate::{
+    sbi::consolo_getchar,
+    task::{cur_killed, suspend_cur_and_sleep},
+};
 
 use super::File;
 
+/// interval of polling console input
+const POLL_MS: usize = 10;
+
 /// standard input
 pub struct Stdin;
 
@@ -13,13 +19,17 @@ impl File for Stdin {
         // read a char once
         assert_eq!(buf.len(), 1);
 
-        // busy quiering
+        // SBI console has no interrupt, so we poll it
         let mut c;
 
         c = consolo_getchar();
         while c == 0 {
-            // don't get a char, switch task and try latter
-            suspend_cur_and_run_next();
+            // killed task exits when it returns to user
+            if cur_killed() {
+                return 0;
+            }
+            // don't get a char, sleep and try latter
+            suspend_cur_and_sleep(POLL_MS);
             c = consolo_getchar();
         }
 
@@ -0,0 +1,78 @@
+//! # klog
+//!
+//! Kernel log ring buffer. Every log message is kept here as a text record
+//! `[seconds.micros] TAG message\n`, the oldest records are overwritten when
+//! the buffer is full. `sys_syslog` copies it to user, see `dmesg`.
+
+use crate::config::LOG_BUF_SIZE;
+use crate::timer::time_us;
+use crate::trap::{disable_kernel_interrupt, restore_kernel_interrupt};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+struct LogBuf {
+    buf: [u8; LOG_BUF_SIZE],
+    /// bytes ever written, `written % LOG_BUF_SIZE` is the next position
+    written: usize,
+}
+
+impl Write for LogBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            self.buf[self.written % LOG_BUF_SIZE] = b;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+impl LogBuf {
+    /// records in the buffer from the oldest, partly overwritten record is dropped
+    fn contents(&self) -> Vec<u8> {
+        if self.written <= LOG_BUF_SIZE {
+            return self.buf[..self.written].to_vec();
+        }
+        let start = self.written % LOG_BUF_SIZE;
+        let mut v = Vec::with_capacity(LOG_BUF_SIZE);
+        v.extend_from_slice(&self.buf[start..]);
+        v.extend_from_slice(&self.buf[..start]);
+        match v.iter().position(|&b| b == b'\n') {
+            Some(pos) => v.split_off(pos + 1),
+            None => Vec::new(),
+        }
+    }
+}
+
+// not in `lazy_static!`, so it lives in .bss instead of kernel heap
+static LOG_BUF: Mutex<LogBuf> = Mutex::new(LogBuf {
+    buf: [0; LOG_BUF_SIZE],
+    written: 0,
+});
+
+/// append a record with current time to log buffer
+pub fn record(tag: &str, args: fmt::Arguments) {
+    let us = time_us();
+    // records may come from interrupt handler, which would spin on LOG_BUF
+    let sie = disable_kernel_interrupt();
+    let mut log_buf = LOG_BUF.lock();
+    log_buf
+        .write_fmt(format_args!(
+            "[{:>5}.{:06}] {} {}\n",
+            us / 1_000_000,
+            us % 1_000_000,
+            tag,
+            args
+        ))
+        .unwrap();
+    drop(log_buf);
+    restore_kernel_interrupt(sie);
+}
+
+/// @return records in log buffer, from the oldest
+pub fn read_all() -> Vec<u8> {
+    let sie = disable_kernel_interrupt();
+    let contents = LOG_BUF.lock().contents();
+    restore_kernel_interrupt(sie);
+    contents
+}
@@ -0,0 +1,77 @@
+//! # Kernel symbol table
+//!
+//! Symbol table is generated by `build.rs` and placed in `.ksymtab`, see
+//! `build.rs` for its layout. It is empty when kernel isn't built by Makefile.
+
+use crate::config::{eksymtab, sksymtab};
+
+const KSYMTAB_LEN: usize = include_bytes!(concat!(env!("OUT_DIR"), "/ksymtab.bin")).len();
+
+/// we never read it directly, otherwise compiler knows its content and the
+/// code will change between two builds, use `sksymtab` instead
+#[used]
+#[link_section = ".ksymtab"]
+static KSYMTAB: [u8; KSYMTAB_LEN] = *include_bytes!(concat!(env!("OUT_DIR"), "/ksymtab.bin"));
+
+fn table() -> &'static [u8] {
+    let start = sksymtab as usize;
+    let end = eksymtab as usize;
+    unsafe { core::slice::from_raw_parts(start as *const u8, end - start) }
+}
+
+fn read_u64(table: &[u8], offset: usize) -> usize {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&table[offset..offset + 8]);
+    u64::from_le_bytes(bytes) as usize
+}
+
+fn read_u32(table: &[u8], offset: usize) -> usize {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&table[offset..offset + 4]);
+    u32::from_le_bytes(bytes) as usize
+}
+
+/// find the symbol that contains `addr`
+fn find(table: &'static [u8], addr: usize) -> Option<(&'static str, usize)> {
+    if table.len() < 8 {
+        return None;
+    }
+    let count = read_u64(table, 0);
+    let entry = |i: usize| 8 + i * 16;
+
+    // find the last symbol whose address <= addr
+    let (mut l, mut r) = (0, count);
+    while l < r {
+        let mid = (l + r) / 2;
+        if read_u64(table, entry(mid)) <= addr {
+            l = mid + 1;
+        } else {
+            r = mid;
+        }
+    }
+    if l == 0 {
+        return None;
+    }
+
+    let e = entry(l - 1);
+    let sym_addr = read_u64(table, e);
+    let name_offset = read_u32(table, e + 8);
+    let name_len = read_u32(table, e + 12);
+    let name = core::str::from_utf8(&table[name_offset..name_offset + name_len]).ok()?;
+    Some((name, addr - sym_addr))
+}
+
+/// # lookup
+/// @return (symbol name, offset from symbol) of `addr`
+///
+/// Table of a stale build gives wrong names, so we check it with `__main`
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    extern "C" {
+        fn __main();
+    }
+    let table = table();
+    match find(table, __main as usize) {
+        Some(("__main", 0)) => find(table, addr),
+        _ => None,
+    }
+}
@@ -1,11 +1,13 @@
+use crate::backtrace::backtrace;
 use crate::sbi::shutdown;
 use core::panic::PanicInfo;
 
 #[panic_handler]
 pub fn panic(info: &PanicInfo) -> ! {
+    // don't use `error!`, panic message must be printed whatever log level is
     if let Some(location) = info.location() {
-        error!(
-            "!!> Panic at {}:{} {}",
+        println!(
+            "\x1b[31m!!> Panic at {}:{} {}\x1b[0m",
             location.file(),
             location.line(),
             info.message().unwrap()
@@ -13,6 +15,7 @@ pub fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("!!> Panic: {}", info.message().unwrap());
     }
+    backtrace();
 
     shutdown()
 }
@@ -6,12 +6,16 @@
 
 #[macro_use]
 mod console;
+mod backtrace;
 mod config;
 mod drivers;
 mod fs;
+mod klog;
+mod ksym;
 mod lang_item;
 mod mm;
 mod orca_logo;
+mod profile;
 mod sbi;
 mod sync;
 mod syscall;
@@ -61,10 +65,10 @@ pub fn __main() {
 
     debug!("trap init");
     trap::init();
+    drivers::plic::init();
 
     debug!("start timer");
-    trap::enable_timer_interrupt();
-    timer::set_strigger();
+    timer::init();
 
     /* show system info */
     sys_info();
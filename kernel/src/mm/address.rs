@@ -24,32 +24,39 @@ pub struct PhysPageNum(pub usize);
 #[repr(C)]
 pub struct VirtPageNum(pub usize);
 
-const PA_WIDTH_SV39: usize = 56;
-const VA_WIDTH_SV39: usize = 39;
-const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_OFFSET;
-const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_OFFSET;
+// paging mode is chosen by cargo feature `sv48`, default: Sv39
+#[cfg(not(feature = "sv48"))]
+const VA_WIDTH: usize = 39;
+#[cfg(feature = "sv48")]
+const VA_WIDTH: usize = 48;
+/// levels of page table walk
+pub const PAGE_LEVELS: usize = (VA_WIDTH - PAGE_OFFSET) / 9;
+
+const PA_WIDTH: usize = 56;
+const PPN_WIDTH: usize = PA_WIDTH - PAGE_OFFSET;
+const VPN_WIDTH: usize = VA_WIDTH - PAGE_OFFSET;
 
 impl From<usize> for PhysAddr {
     fn from(v: usize) -> Self {
-        Self(v & ((1 << PA_WIDTH_SV39) - 1))
+        Self(v & ((1 << PA_WIDTH) - 1))
     }
 }
 
 impl From<usize> for PhysPageNum {
     fn from(v: usize) -> Self {
-        Self(v & ((1 << PPN_WIDTH_SV39) - 1))
+        Self(v & ((1 << PPN_WIDTH) - 1))
     }
 }
 
 impl From<usize> for VirtAddr {
     fn from(v: usize) -> Self {
-        Self(v & ((1 << VA_WIDTH_SV39) - 1))
+        Self(v & ((1 << VA_WIDTH) - 1))
     }
 }
 
 impl From<usize> for VirtPageNum {
     fn from(v: usize) -> Self {
-        Self(v & ((1 << VPN_WIDTH_SV39) - 1))
+        Self(v & ((1 << VPN_WIDTH) - 1))
     }
 }
 
@@ -160,10 +167,11 @@ impl PhysPageNum {
 }
 
 impl VirtPageNum {
-    pub fn indexes(&self) -> [usize; 3] {
+    /// index in each level of page table, from root to leaf
+    pub fn indexes(&self) -> [usize; PAGE_LEVELS] {
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
+        let mut idx = [0usize; PAGE_LEVELS];
+        for i in (0..PAGE_LEVELS).rev() {
             idx[i] = vpn & ((1 << 9) - 1);
             vpn >>= 9;
         }
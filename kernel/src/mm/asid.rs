@@ -0,0 +1,129 @@
+//! # ASID
+//!
+//! Every user page table gets an address space id, which is put in its satp
+//! token, so that TLB entries of different address spaces live together and
+//! switching satp needs no flush. Kernel space keeps ASID 0.
+//!
+//! Ids are handed out in generations. When a generation runs out of ids, a
+//! new one starts with the whole TLB flushed, and page tables of older
+//! generations take a new id the next time they're switched to. An id is
+//! never reused within a generation, so freed page tables need no flush.
+//!
+//! Hart without ASID runs every user space with ASID 0, then trampoline
+//! flushes the whole TLB on every switch as before.
+
+use core::arch::asm;
+use lazy_static::lazy_static;
+use riscv::register::satp;
+
+use crate::sync::UniProcSafeCell;
+
+/// ASID field of satp, same in Sv39 and Sv48
+const ASID_SHIFT: usize = 44;
+const ASID_MASK: usize = 0xffff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Asid {
+    generation: usize,
+    id: usize,
+}
+
+impl Asid {
+    /// page table never switched to, it has nothing in TLB
+    pub const NONE: Self = Self {
+        generation: 0,
+        id: 0,
+    };
+
+    /// kernel space, which is never switched out of ASID 0
+    pub const KERNEL: Self = Self {
+        generation: usize::MAX,
+        id: 0,
+    };
+
+    /// bits of `id` in satp
+    pub fn satp_bits(&self) -> usize {
+        self.id << ASID_SHIFT
+    }
+}
+
+struct AsidAllocator {
+    /// starts from 1, 0 is generation of `Asid::NONE`
+    generation: usize,
+    next: usize,
+    /// largest id supported by hart, 0 if it has no ASID
+    max: usize,
+}
+
+lazy_static! {
+    static ref ASID_ALLOCATOR: UniProcSafeCell<AsidAllocator> =
+        UniProcSafeCell::new(AsidAllocator {
+            generation: 1,
+            next: 1,
+            max: 0,
+        });
+}
+
+/// # init
+/// find out how many ASID bits the hart has, by writing all ones to the
+/// field and reading it back. Must be called in kernel space
+pub fn init() {
+    let token = satp::read().bits();
+    unsafe {
+        satp::write(token | ASID_MASK << ASID_SHIFT);
+        let max = satp::read().asid();
+        satp::write(token);
+        asm!("sfence.vma");
+        ASID_ALLOCATOR.borrow_mut().max = max;
+    }
+    kernel!(
+        "asid: {} ids for user space",
+        ASID_ALLOCATOR.borrow_mut().max
+    );
+}
+
+/// # refresh
+/// make sure `asid` belongs to current generation before it's switched to
+pub fn refresh(asid: &mut Asid) {
+    let mut allocator = ASID_ALLOCATOR.borrow_mut();
+    if allocator.max == 0 {
+        // hart without ASID, trampoline flushes TLB on every switch
+        return;
+    }
+    if asid.id != 0 && asid.generation == allocator.generation {
+        return;
+    }
+    if allocator.next > allocator.max {
+        // every id is used, start a new generation, TLB of old ones is gone
+        allocator.generation += 1;
+        allocator.next = 1;
+        unsafe { asm!("sfence.vma") };
+    }
+    *asid = Asid {
+        generation: allocator.generation,
+        id: allocator.next,
+    };
+    allocator.next += 1;
+}
+
+/// # flush_page
+/// flush TLB of page at `va` tagged by `asid`
+pub fn flush_page(asid: Asid, va: usize) {
+    let generation = ASID_ALLOCATOR.borrow_mut().generation;
+    unsafe {
+        if asid == Asid::KERNEL {
+            asm!("sfence.vma {}, zero", in(reg) va);
+        } else if asid.id != 0 && asid.generation == generation {
+            asm!("sfence.vma {}, {}", in(reg) va, in(reg) asid.id);
+        }
+        // otherwise nothing of it is in TLB: never switched to, or flushed
+        // when its generation ended
+    }
+}
+
+/// # flush_kernel
+/// flush TLB of kernel space, tagged by ASID 0, TLB of user spaces is kept
+pub fn flush_kernel() {
+    // rs2 must be a register holding 0, `zero` would flush every ASID
+    unsafe { asm!("sfence.vma zero, {}", in(reg) 0) };
+}
@@ -0,0 +1,36 @@
+//! # ASLR
+//!
+//! Address space layout randomization: every exec places program, user stack
+//! and mmap area at random page offsets, so addresses of one run tell nothing
+//! about another. Program is only moved when it's built as PIE, user heap
+//! lives in its bss and moves with it.
+//!
+//! Entropy comes from `time` csr, which is mixed into a xorshift state on
+//! every draw. It's enough to scatter addresses, not for secrets.
+//!
+//! Build with cargo feature `no_aslr` to get the same layout on every run.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::timer::time;
+
+static STATE: AtomicUsize = AtomicUsize::new(0x2545_f491_4f6c_dd1d);
+
+fn random() -> usize {
+    let mut x = STATE.load(Ordering::Relaxed) ^ time();
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    x
+}
+
+/// # random_pages
+/// random number of pages below `2^bits`, always 0 with `no_aslr`
+pub fn random_pages(bits: usize) -> usize {
+    if cfg!(feature = "no_aslr") {
+        0
+    } else {
+        random() & ((1 << bits) - 1)
+    }
+}
@@ -3,81 +3,181 @@ use super::address::PhysPageNum;
 use crate::config::ekernel;
 use crate::config::MEMORY_END;
 use crate::sync::UniProcSafeCell;
+use crate::task::oom::oom_kill;
+use alloc::vec;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 
 trait FrameAllocator {
     fn new() -> Self;
-    fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn dealloc(&mut self, ppn: PhysPageNum);
+    fn alloc(&mut self, order: usize) -> Option<PhysPageNum>;
+    fn dealloc(&mut self, ppn: PhysPageNum, order: usize);
+}
+
+/// largest block is `2^MAX_ORDER` frames
+pub const MAX_ORDER: usize = 10;
+
+/// end of free list
+const NIL: usize = usize::MAX;
+
+/// state of a frame in `meta`, lower bits are order of the block
+const HEAD_FREE: u8 = 0x40;
+const HEAD_ALLOCATED: u8 = 0x80;
+/// frame inside a block but not its first frame
+const TAIL: u8 = 0;
+
+/// statistics of frame allocator, in frames
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    /// successful allocations and deallocations
+    pub allocs: usize,
+    pub deallocs: usize,
+    /// allocations failed for no block large enough
+    pub failures: usize,
+    /// number of free blocks of each order
+    pub free_blocks: [usize; MAX_ORDER + 1],
 }
 
 /// # physical frame allocator
 /// alloc physical frame from ram
 ///
-/// ## alloc
-/// @return `Option<PhysPageNum>`
-///
-/// We use a simple stack to alloc frame.
-/// - if user return frame, push it into a vector
-/// - if there is no frame that return, from user, `cur++`
-/// - if there is some frames released, use it from vector
+/// Buddy system: free frames are kept in blocks of `2^order` frames, one free
+/// list per order. A block is split when a smaller one is needed, and merged
+/// with its buddy when both are free.
 ///
-/// ## dealloc
-/// release PhysPageNum
+/// - free lists are doubly linked through the first two words of free frames,
+///   so the allocator itself needs no memory besides `meta`
+/// - `meta` keeps one byte per frame, which tells if a frame is head of a free
+///   or allocated block and its order, so double free is found in O(1)
 ///
-pub struct StackFrameAllocator {
-    cur: usize,
-    end: usize,
-    recycled: Vec<usize>,
+/// Frame numbers here are relative to `base`.
+pub struct BuddyFrameAllocator {
+    base: usize,
+    meta: Vec<u8>,
+    free_lists: [usize; MAX_ORDER + 1],
+    stats: FrameStats,
 }
 
-impl StackFrameAllocator {
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.cur = l.0;
-        self.end = r.0;
+        self.base = l.0;
+        let total = r.0 - l.0;
+        self.meta = vec![TAIL; total];
+        self.stats.total = total;
+
+        // cut memory into the largest aligned blocks
+        let mut idx = 0;
+        while idx < total {
+            let mut order = MAX_ORDER;
+            while idx % (1 << order) != 0 || idx + (1 << order) > total {
+                order -= 1;
+            }
+            self.push(idx, order);
+            self.stats.free += 1 << order;
+            idx += 1 << order;
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
     }
-    pub fn empty(&self) -> bool {
-        self.cur == self.end
+
+    /// the two words to link free list, in the free frame itself
+    fn links(&self, idx: usize) -> &'static mut [usize; 2] {
+        let pa: PhysAddr = PhysPageNum(self.base + idx).into();
+        unsafe { &mut *(pa.0 as *mut [usize; 2]) }
     }
 
-    fn is_valid(&self, ppn: PhysPageNum) -> bool {
-        let ppn = ppn.0;
-        ppn < self.cur && !self.recycled.iter().any(|v| *v == ppn)
+    fn push(&mut self, idx: usize, order: usize) {
+        let head = self.free_lists[order];
+        *self.links(idx) = [head, NIL];
+        if head != NIL {
+            self.links(head)[1] = idx;
+        }
+        self.free_lists[order] = idx;
+        self.meta[idx] = HEAD_FREE | order as u8;
+        self.stats.free_blocks[order] += 1;
+    }
+
+    fn remove(&mut self, idx: usize, order: usize) {
+        let [next, prev] = *self.links(idx);
+        if prev == NIL {
+            self.free_lists[order] = next;
+        } else {
+            self.links(prev)[0] = next;
+        }
+        if next != NIL {
+            self.links(next)[1] = prev;
+        }
+        self.meta[idx] = TAIL;
+        self.stats.free_blocks[order] -= 1;
     }
 }
 
-impl FrameAllocator for StackFrameAllocator {
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            cur: 0,
-            end: 0,
-            recycled: Vec::new(),
+            base: 0,
+            meta: Vec::new(),
+            free_lists: [NIL; MAX_ORDER + 1],
+            stats: FrameStats::default(),
         }
     }
 
-    fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.empty() {
-            None
-        } else {
-            self.cur += 1;
-            Some((self.cur - 1).into())
+    fn alloc(&mut self, order: usize) -> Option<PhysPageNum> {
+        let found = (order..=MAX_ORDER).find(|&o| self.free_lists[o] != NIL);
+        let mut cur = match found {
+            Some(o) => o,
+            None => {
+                self.stats.failures += 1;
+                return None;
+            }
+        };
+        let idx = self.free_lists[cur];
+        self.remove(idx, cur);
+
+        // split, upper half goes back to free list
+        while cur > order {
+            cur -= 1;
+            self.push(idx + (1 << cur), cur);
         }
+
+        self.meta[idx] = HEAD_ALLOCATED | order as u8;
+        self.stats.free -= 1 << order;
+        self.stats.allocs += 1;
+        Some(PhysPageNum(self.base + idx))
     }
 
-    fn dealloc(&mut self, ppn: PhysPageNum) {
-        if self.is_valid(ppn) {
-            let ppn = ppn.0;
-            self.recycled.push(ppn);
-        } else {
-            panic!("Frame ppn={:#x} hasn't been allocated", ppn.0);
+    fn dealloc(&mut self, ppn: PhysPageNum, order: usize) {
+        let mut idx = ppn.0.wrapping_sub(self.base);
+        if idx >= self.meta.len() || self.meta[idx] != HEAD_ALLOCATED | order as u8 {
+            panic!(
+                "Frame ppn={:#x} of order {} hasn't been allocated",
+                ppn.0, order
+            );
+        }
+        self.meta[idx] = TAIL;
+        self.stats.free += 1 << order;
+        self.stats.deallocs += 1;
+
+        // merge with buddy as long as it's free
+        let mut order = order;
+        while order < MAX_ORDER {
+            let buddy = idx ^ (1 << order);
+            if buddy >= self.meta.len() || self.meta[buddy] != HEAD_FREE | order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            idx = idx.min(buddy);
+            order += 1;
         }
+        self.push(idx, order);
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: UniProcSafeCell<FrameAllocatorImpl> =
@@ -89,16 +189,88 @@ pub fn init() {
     FRAME_ALLOCATOR.borrow_mut().init(
         PhysAddr::from(ekernel as usize).ceil(),
         PhysAddr::from(MEMORY_END).floor(),
-    )
+    );
+    let stats = frame_stats();
+    debug!(
+        "frame allocator: {} frames, free blocks of each order: {:?}",
+        stats.total, stats.free_blocks
+    );
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR.borrow_mut().alloc().map(FrameTracker::new)
+    let ppn = FRAME_ALLOCATOR.borrow_mut().alloc(0);
+    ppn.map(FrameTracker::new)
+}
+
+/// # frame_alloc_or_kill
+/// for frames kernel itself can't live without, OOM killer is called
+/// until we get one or there is nothing to kill
+pub fn frame_alloc_or_kill() -> Option<FrameTracker> {
+    loop {
+        if let Some(frame) = frame_alloc() {
+            return Some(frame);
+        }
+        if !oom_kill() {
+            return None;
+        }
+    }
 }
 
 // do not use outside
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.borrow_mut().dealloc(ppn);
+    FRAME_ALLOCATOR.borrow_mut().dealloc(ppn, 0);
+}
+
+/// order of the smallest block that holds `pages` frames
+fn order_of(pages: usize) -> usize {
+    pages.next_power_of_two().trailing_zeros() as usize
+}
+
+/// # frames_alloc_contiguous
+/// alloc `pages` physically contiguous and zeroed frames, e.g. for DMA.
+/// OOM killer is called if memory runs out.
+///
+/// They are not tracked, free them by `frames_dealloc_contiguous` with the same `pages`
+pub fn frames_alloc_contiguous(pages: usize) -> Option<PhysPageNum> {
+    let order = order_of(pages);
+    if order > MAX_ORDER {
+        return None;
+    }
+    loop {
+        let ppn = FRAME_ALLOCATOR.borrow_mut().alloc(order);
+        if let Some(ppn) = ppn {
+            for i in 0..pages {
+                PhysPageNum(ppn.0 + i).bytes_array().fill(0);
+            }
+            return Some(ppn);
+        }
+        if !oom_kill() {
+            return None;
+        }
+    }
+}
+
+pub fn frames_dealloc_contiguous(ppn: PhysPageNum, pages: usize) {
+    FRAME_ALLOCATOR.borrow_mut().dealloc(ppn, order_of(pages));
+}
+
+/// # frames_try_alloc
+/// block of `2^order` frames for kernel heap and slab. `None` if memory runs
+/// out or frame allocator is in use, since they may be called inside it
+pub fn frames_try_alloc(order: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.try_borrow_mut()?.alloc(order)
+}
+
+/// free block from `frames_try_alloc`, false if frame allocator is in use
+pub fn frames_try_dealloc(ppn: PhysPageNum, order: usize) -> bool {
+    FRAME_ALLOCATOR
+        .try_borrow_mut()
+        .map(|mut allocator| allocator.dealloc(ppn, order))
+        .is_some()
+}
+
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.borrow_mut().stats()
 }
 
 /// wraper of PhysPageNum
@@ -1,21 +1,182 @@
-use core::alloc::Layout;
+//! # Kernel heap
+//!
+//! Heap starts with `HEAP_SPACE` in bss. When it runs out, blocks of frames
+//! are taken from frame allocator and added to it, they're reachable through
+//! the direct map of kernel space, so no new mapping is needed. Frames given
+//! to heap are never returned. Each block starts with `HeapBlock`, which links
+//! it to other blocks, so heap knows which addresses are its own.
+//!
+//! Allocations of hot kernel objects are served by `slab` instead, unless it
+//! can't get a frame. Objects of the same layout may be in either of them,
+//! so where one is freed to is decided by its address.
 
-use crate::config::KERNEL_HEAP_SIZE;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::config::{KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::task::oom::oom_kill;
 use buddy_system_allocator::LockedHeap;
 
+use super::address::PhysAddr;
+use super::frame_allocator::{frames_try_alloc, MAX_ORDER};
+use super::slab;
+
+/// heap grows by at least `2^HEAP_GROW_ORDER` frames each time
+const HEAP_GROW_ORDER: usize = 4;
+
+/// head of a block added by `grow`
+struct HeapBlock {
+    end: usize,
+    next: *mut HeapBlock,
+}
+
+/// kernel heap, grow it or call OOM killer and retry when it runs out
+struct KernelHeap {
+    heap: LockedHeap,
+    /// bytes added to heap by `grow`
+    grown: AtomicUsize,
+    /// times heap grows, nothing is logged inside allocator
+    grows: AtomicUsize,
+    /// list of blocks added by `grow`, it's only pushed at head since blocks
+    /// are never returned, so it can be walked without lock
+    blocks: AtomicPtr<HeapBlock>,
+}
+
+impl KernelHeap {
+    /// if `addr` is in `HEAP_SPACE` or a block added by `grow`
+    fn contains(&self, addr: usize) -> bool {
+        let start = unsafe { HEAP_SPACE.as_ptr() as usize };
+        if (start..start + KERNEL_HEAP_SIZE).contains(&addr) {
+            return true;
+        }
+        let mut block = self.blocks.load(Ordering::Acquire);
+        while !block.is_null() {
+            let HeapBlock { end, next } = unsafe { &*block };
+            if (block as usize..*end).contains(&addr) {
+                return true;
+            }
+            block = *next;
+        }
+        false
+    }
+
+    /// # grow
+    /// add a block of frames that surely holds `layout` to heap.
+    ///
+    /// @return false if frame allocator has no such block or is in use,
+    /// since heap may be called inside it
+    fn grow(&self, layout: Layout) -> bool {
+        // a region twice the size contains an aligned block of the size,
+        // even if it starts with `HeapBlock`
+        let size = layout.size().max(layout.align()).next_power_of_two() * 2;
+        let order = (size / PAGE_SIZE)
+            .next_power_of_two()
+            .trailing_zeros()
+            .max(HEAP_GROW_ORDER as u32) as usize;
+        if order > MAX_ORDER {
+            return false;
+        }
+        let ppn = match frames_try_alloc(order) {
+            Some(ppn) => ppn,
+            None => return false,
+        };
+        let start = PhysAddr::from(ppn).0;
+        let end = start + (PAGE_SIZE << order);
+        let block = start as *mut HeapBlock;
+        let mut next = self.blocks.load(Ordering::Relaxed);
+        loop {
+            unsafe { *block = HeapBlock { end, next } };
+            match self
+                .blocks
+                .compare_exchange(next, block, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(head) => next = head,
+            }
+        }
+        let start = start + size_of::<HeapBlock>();
+        unsafe {
+            self.heap.lock().add_to_heap(start, end);
+        }
+        self.grown.fetch_add(end - start, Ordering::Relaxed);
+        self.grows.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        loop {
+            let ptr = match slab::alloc(layout) {
+                Some(ptr) => ptr,
+                None => {
+                    let ptr = self.heap.alloc(layout);
+                    if ptr.is_null() && self.grow(layout) {
+                        self.heap.alloc(layout)
+                    } else {
+                        ptr
+                    }
+                }
+            };
+            if !ptr.is_null() || !oom_kill() {
+                return ptr;
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.contains(ptr as usize) || !slab::dealloc(ptr, layout) {
+            self.heap.dealloc(ptr, layout)
+        }
+    }
+}
+
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: KernelHeap = KernelHeap {
+    heap: LockedHeap::empty(),
+    grown: AtomicUsize::new(0),
+    grows: AtomicUsize::new(0),
+    blocks: AtomicPtr::new(null_mut()),
+};
 
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
 pub fn init() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap
             .lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+/// statistics of kernel heap, in bytes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapStats {
+    pub total: usize,
+    /// bytes taken by allocations, including rounding up by buddy system
+    pub used: usize,
+    /// bytes asked by allocations
+    pub requested: usize,
+    /// part of `total` taken from frame allocator
+    pub grown: usize,
+    /// times heap grows
+    pub grows: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.heap.lock();
+    HeapStats {
+        total: heap.stats_total_bytes(),
+        used: heap.stats_alloc_actual(),
+        requested: heap.stats_alloc_user(),
+        grown: HEAP_ALLOCATOR.grown.load(Ordering::Relaxed),
+        grows: HEAP_ALLOCATOR.grows.load(Ordering::Relaxed),
+    }
+}
+
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap alloc error, layout = {:?}", layout);
@@ -1,17 +1,30 @@
 use core::arch::asm;
 
 use crate::{
-    config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE},
+    config::{
+        ASLR_LOAD_BITS, ASLR_MMAP_BITS, ASLR_STACK_BITS, MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE,
+        TRAP_CONTEXT, USER_MMAP_BASE, USER_MMAP_END, USER_STACK_GUARD, USER_STACK_LIMIT,
+        USER_STACK_SIZE,
+    },
+    fs::page_cache::{self, FilePage},
     sync::UniProcSafeCell,
 };
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use easy_fs::vfs::Inode;
 use lazy_static::lazy_static;
 use riscv::register::satp;
 
 use super::{
     address::{PhysAddr, PhysPageNum, StepByOne, VPNRange, VirtAddr, VirtPageNum},
+    aslr::random_pages,
     frame_allocator::{frame_alloc, FrameTracker},
-    page_table::{PTEFlags, PageTable, PageTableEntry},
+    page_table::{PTEFlags, PageSize, PageTable, PageTableEntry},
+    shm::ShmSegment,
+    MmError, MmResult,
 };
 
 // import position of differnet sections
@@ -31,6 +44,30 @@ pub struct MapArea {
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// segment whose frames are mapped, only for `MapType::Shared`
+    shared: Option<Arc<ShmSegment>>,
+    /// page index in `shared` or `file` that start of area maps
+    pgoff: usize,
+    /// file whose pages are mapped, only for `MapType::File`
+    file: Option<FileMapping>,
+    /// pages of page cache mapped by `MapType::File` area, pages copied on
+    /// write of private mapping are kept in `data_frames` instead
+    file_pages: BTreeMap<VirtPageNum, Arc<FilePage>>,
+    /// pages of shared file mapping written through this area since they
+    /// were synced, other mappings of the same page keep their own
+    dirty_pages: BTreeSet<VirtPageNum>,
+}
+
+/// file backing a `MapType::File` area
+#[derive(Clone)]
+pub struct FileMapping {
+    pub inode: Arc<Inode>,
+    /// `MAP_SHARED` writes reach file when they're synced, `MAP_PRIVATE`
+    /// writes copy the page
+    pub shared: bool,
+    /// if file is opened for write, shared mapping of a file that's not
+    /// can't be made writable
+    pub writable: bool,
 }
 
 impl MapArea {
@@ -47,16 +84,77 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            shared: None,
+            pgoff: 0,
+            file: None,
+            file_pages: BTreeMap::new(),
+            dirty_pages: BTreeSet::new(),
+        }
+    }
+
+    /// area mapping `file` from page `pgoff` at `va_start`, pages are
+    /// mapped on page fault
+    pub fn new_file(
+        va_start: VirtAddr,
+        va_end: VirtAddr,
+        file: FileMapping,
+        pgoff: usize,
+        map_perm: MapPermission,
+    ) -> Self {
+        let mut area = Self::new(va_start, va_end, MapType::File, map_perm);
+        area.pgoff = pgoff;
+        area.file = Some(file);
+        area
+    }
+
+    /// area mapping the whole `segment` from `va_start`
+    pub fn new_shared(
+        va_start: VirtAddr,
+        segment: Arc<ShmSegment>,
+        map_perm: MapPermission,
+    ) -> Self {
+        let va_start: VirtPageNum = va_start.floor();
+        let va_end = VirtPageNum(va_start.0 + segment.pages());
+        Self {
+            vpn_range: VPNRange::new(va_start, va_end),
+            data_frames: BTreeMap::new(),
+            map_type: MapType::Shared,
+            map_perm,
+            shared: Some(segment),
+            pgoff: 0,
+            file: None,
+            file_pages: BTreeMap::new(),
+            dirty_pages: BTreeSet::new(),
         }
     }
 
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// pages mapped are unmapped again if it fails
+    pub fn map(&mut self, page_table: &mut PageTable) -> MmResult {
+        if self.map_type == MapType::File {
+            // mapped on page fault
+            return Ok(());
+        }
+        if self.map_type == MapType::Identical {
+            return self.map_identical(page_table);
+        }
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if let Err(err) = self.map_one(page_table, vpn) {
+                for mapped in VPNRange::new(self.vpn_range.start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return Err(err);
+            }
         }
+        Ok(())
     }
 
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Identical {
+            for (vpn, size) in self.identical_pages() {
+                page_table.unmap_huge(vpn, size);
+            }
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
@@ -79,28 +177,260 @@ impl MapArea {
         }
     }
 
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> MmResult {
         let ppn: PhysPageNum;
+        let mut frame = None;
         match self.map_type {
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let f = frame_alloc().ok_or(MmError::OutOfMemory)?;
+                ppn = f.ppn;
+                frame = Some(f);
             }
             MapType::Identical => ppn = PhysPageNum(vpn.0),
+            MapType::Shared => {
+                let segment = self.shared.as_ref().unwrap();
+                ppn = segment.ppn(self.pgoff + vpn.0 - self.vpn_range.start().0);
+            }
+            MapType::File => panic!("file page {:?} is mapped by fault", vpn),
         }
 
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        page_table.map(vpn, ppn, pte_flags)?;
+        // only keep the frame after it's mapped, so failure leaves nothing behind
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, frame);
+        }
+        Ok(())
     }
 
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if MapType::Framed == self.map_type {
-            self.data_frames.remove(&vpn);
+        match self.map_type {
+            MapType::Framed => {
+                self.data_frames.remove(&vpn);
+            }
+            MapType::File => {
+                self.data_frames.remove(&vpn);
+                self.file_pages.remove(&vpn);
+                self.dirty_pages.remove(&vpn);
+                if !is_present(page_table, vpn) {
+                    return;
+                }
+            }
+            _ => {}
         }
 
         page_table.unmap(vpn);
     }
+
+    /// # identical_pages
+    /// identical area is mapped by pages as large as possible, 2 MiB or 1 GiB
+    /// pages save page table frames and TLB entries
+    fn identical_pages(&self) -> Vec<(VirtPageNum, PageSize)> {
+        let mut pages = Vec::new();
+        let mut vpn = self.vpn_range.start();
+        let end = self.vpn_range.end();
+        while vpn < end {
+            let size = PageSize::fit(vpn, PhysPageNum(vpn.0), end.0 - vpn.0);
+            pages.push((vpn, size));
+            vpn = VirtPageNum(vpn.0 + size.pages());
+        }
+        pages
+    }
+
+    fn map_identical(&mut self, page_table: &mut PageTable) -> MmResult {
+        let flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let pages = self.identical_pages();
+        for (i, &(vpn, size)) in pages.iter().enumerate() {
+            if let Err(err) = page_table.map_huge(vpn, PhysPageNum(vpn.0), flags, size) {
+                for &(mapped, size) in pages[..i].iter() {
+                    page_table.unmap_huge(mapped, size);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// # pte_flags
+    /// flags of page `vpn`. Page of page cache is read only unless it's
+    /// dirty in this area, so that the first write faults, which copies it
+    /// for private mapping or marks it dirty for shared one
+    fn pte_flags(&self, vpn: VirtPageNum) -> PTEFlags {
+        let flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        if self.file_pages.contains_key(&vpn) && !self.dirty_pages.contains(&vpn) {
+            flags - PTEFlags::W
+        } else {
+            flags
+        }
+    }
+
+    /// # handle_file_fault
+    /// map page `vpn` of file area on access, `write` tells if it's a write
+    pub fn handle_file_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        write: bool,
+    ) -> MmResult {
+        let file = self.file.clone().unwrap();
+        let present = is_present(page_table, vpn);
+        if self.data_frames.contains_key(&vpn) || (present && !write) {
+            // private copy or readable page, the access itself isn't allowed
+            return Err(MmError::InvalidArgument);
+        }
+        let page = match self.file_pages.get(&vpn) {
+            Some(page) => page.clone(),
+            None => {
+                let index = self.pgoff + vpn.0 - self.vpn_range.start().0;
+                page_cache::get_page(&file.inode, index)?
+            }
+        };
+
+        if write && !file.shared {
+            // copy on write, the copy is owned by this area only
+            let frame = frame_alloc().ok_or(MmError::OutOfMemory)?;
+            frame
+                .ppn
+                .bytes_array()
+                .copy_from_slice(page.ppn().bytes_array());
+            if present {
+                page_table.unmap(vpn);
+            }
+            self.file_pages.remove(&vpn);
+            let flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+            page_table.map(vpn, frame.ppn, flags)?;
+            self.data_frames.insert(vpn, frame);
+            return Ok(());
+        }
+
+        if write {
+            self.dirty_pages.insert(vpn);
+        }
+        let ppn = page.ppn();
+        self.file_pages.insert(vpn, page);
+        let flags = self.pte_flags(vpn);
+        if present {
+            page_table.set_flags(vpn, flags);
+        } else if let Err(err) = page_table.map(vpn, ppn, flags) {
+            self.file_pages.remove(&vpn);
+            self.dirty_pages.remove(&vpn);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// # sync_file
+    /// write pages in `[start, end)` dirty in this area back to file,
+    /// they're made read only again to catch the next write
+    pub fn sync_file(&mut self, page_table: &mut PageTable, start: VirtPageNum, end: VirtPageNum) {
+        let dirty: Vec<VirtPageNum> = self.dirty_pages.range(start..end).copied().collect();
+        for vpn in dirty {
+            self.file_pages[&vpn].sync();
+            self.dirty_pages.remove(&vpn);
+            page_table.set_flags(vpn, self.pte_flags(vpn));
+        }
+    }
+
+    /// map pages in `[new_start, start)` and make them part of this area.
+    /// If memory runs out, area only grows to the lowest page mapped
+    pub fn extend_down(&mut self, page_table: &mut PageTable, new_start: VirtPageNum) -> MmResult {
+        while self.vpn_range.start() > new_start {
+            let vpn = VirtPageNum(self.vpn_range.start().0 - 1);
+            self.map_one(page_table, vpn)?;
+            self.vpn_range = VPNRange::new(vpn, self.vpn_range.end());
+        }
+        Ok(())
+    }
+
+    /// number of frames owned by this area
+    pub fn frame_count(&self) -> usize {
+        self.data_frames.len()
+    }
+
+    /// # split_off
+    /// split area into `[start, at)` and `[at, end)`, this area keeps the
+    /// former and the latter is returned with its frames
+    pub fn split_off(&mut self, at: VirtPageNum) -> Self {
+        let (start, end) = (self.vpn_range.start(), self.vpn_range.end());
+        assert!(start < at && at < end, "split {:?} out of area", at);
+        self.vpn_range = VPNRange::new(start, at);
+        Self {
+            vpn_range: VPNRange::new(at, end),
+            data_frames: self.data_frames.split_off(&at),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            shared: self.shared.clone(),
+            pgoff: self.pgoff + at.0 - start.0,
+            file: self.file.clone(),
+            file_pages: self.file_pages.split_off(&at),
+            dirty_pages: self.dirty_pages.split_off(&at),
+        }
+    }
+
+    /// if `next`, which starts where this area ends, maps what follows
+    /// this area, so they can be one area
+    fn can_merge(&self, next: &MapArea) -> bool {
+        let pages = self.vpn_range.end().0 - self.vpn_range.start().0;
+        let contiguous = next.pgoff == self.pgoff + pages;
+        self.vpn_range.end() == next.vpn_range.start()
+            && self.map_type == next.map_type
+            && self.map_perm == next.map_perm
+            && match self.map_type {
+                MapType::Framed => true,
+                MapType::Shared => {
+                    let (a, b) = (self.shared.as_ref().unwrap(), next.shared.as_ref().unwrap());
+                    Arc::ptr_eq(a, b) && contiguous
+                }
+                MapType::File => {
+                    let (a, b) = (self.file.as_ref().unwrap(), next.file.as_ref().unwrap());
+                    Arc::ptr_eq(&a.inode, &b.inode)
+                        && a.shared == b.shared
+                        && a.writable == b.writable
+                        && contiguous
+                }
+                MapType::Identical => false,
+            }
+    }
+
+    /// take pages of `next`, which `can_merge`
+    fn merge(&mut self, mut next: MapArea) {
+        self.vpn_range = VPNRange::new(self.vpn_range.start(), next.vpn_range.end());
+        self.data_frames.append(&mut next.data_frames);
+        self.file_pages.append(&mut next.file_pages);
+        self.dirty_pages.append(&mut next.dirty_pages);
+    }
+
+    /// rewrite flags of every page in area
+    pub fn set_perm(&mut self, page_table: &mut PageTable, map_perm: MapPermission) {
+        self.map_perm = map_perm;
+        for vpn in self.vpn_range {
+            // file pages not faulted yet get permission when they're mapped
+            if self.map_type != MapType::File || is_present(page_table, vpn) {
+                page_table.set_flags(vpn, self.pte_flags(vpn));
+            }
+        }
+    }
+
+    /// # try_clone_file
+    /// copy of file area for fork, pages of page cache are shared and
+    /// private copies are copied again. Child starts with no dirty page,
+    /// its writes are caught by itself
+    fn try_clone_file(&self, page_table: &mut PageTable) -> MmResult<Self> {
+        let mut area = self.clone();
+        for (vpn, page) in self.file_pages.iter() {
+            area.file_pages.insert(*vpn, page.clone());
+            page_table.map(*vpn, page.ppn(), area.pte_flags(*vpn))?;
+        }
+        for (vpn, frame) in self.data_frames.iter() {
+            let copy = frame_alloc().ok_or(MmError::OutOfMemory)?;
+            copy.ppn
+                .bytes_array()
+                .copy_from_slice(frame.ppn.bytes_array());
+            page_table.map(*vpn, copy.ppn, self.pte_flags(*vpn))?;
+            area.data_frames.insert(*vpn, copy);
+        }
+        Ok(area)
+    }
 }
 
 impl Clone for MapArea {
@@ -110,6 +440,11 @@ impl Clone for MapArea {
             data_frames: BTreeMap::new(),
             map_type: self.map_type,
             map_perm: self.map_perm,
+            shared: self.shared.clone(),
+            pgoff: self.pgoff,
+            file: self.file.clone(),
+            file_pages: BTreeMap::new(),
+            dirty_pages: BTreeSet::new(),
         }
     }
 }
@@ -118,6 +453,16 @@ impl Clone for MapArea {
 pub enum MapType {
     Identical,
     Framed,
+    /// frames of a shared memory segment, see `shm`
+    Shared,
+    /// pages of a file mapped by `mmap`, see `page_cache`
+    File,
+}
+
+fn is_present(page_table: &PageTable, vpn: VirtPageNum) -> bool {
+    page_table
+        .translate(vpn)
+        .map_or(false, |pte| pte.is_valid())
 }
 
 bitflags! {
@@ -129,29 +474,54 @@ bitflags! {
     }
 }
 
+/// result of a page fault that may hit the user stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackFault {
+    /// stack has grown to cover the faulting address
+    Grown,
+    /// faulting address lies in the guard page below the stack limit
+    Overflow,
+    /// fault has nothing to do with the user stack
+    NotStack,
+    /// no memory to grow the stack
+    OutOfMemory,
+}
+
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// range that user stack may grow into, the page below it is guard page
+    user_stack: Option<VPNRange>,
+    /// shared memory and file mappings are placed from here
+    mmap_base: VirtPageNum,
 }
 
 impl MemorySet {
-    pub fn new() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    pub fn new() -> MmResult<Self> {
+        Ok(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            user_stack: None,
+            mmap_base: VirtAddr::from(USER_MMAP_BASE).floor(),
+        })
     }
 
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
 
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// token to write to satp when switching to this address space
+    pub fn fresh_token(&mut self) -> usize {
+        self.page_table.fresh_token()
+    }
+
+    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> MmResult {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
         self.areas.push(map_area);
+        Ok(())
     }
 
     /// Must assume there is no conflict
@@ -160,16 +530,26 @@ impl MemorySet {
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
+    ) -> MmResult {
         self.push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
         )
     }
 
+    /// kernel can't boot without memory, so we just panic if it fails
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new();
-        memory_set.map_trampoline();
+        Self::try_new_kernel().expect("no memory for kernel space")
+    }
+
+    fn try_new_kernel() -> MmResult<Self> {
+        let mut memory_set = Self {
+            page_table: PageTable::new_kernel()?,
+            areas: Vec::new(),
+            user_stack: None,
+            mmap_base: VirtAddr::from(USER_MMAP_BASE).floor(),
+        };
+        memory_set.map_trampoline()?;
 
         kernel!("mapping .text section");
         memory_set.push(
@@ -180,7 +560,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::X,
             ),
             None,
-        );
+        )?;
 
         kernel!("mapping .rodata section");
         memory_set.push(
@@ -191,7 +571,7 @@ impl MemorySet {
                 MapPermission::R,
             ),
             None,
-        );
+        )?;
 
         kernel!("mapping .data section");
         memory_set.push(
@@ -202,7 +582,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
 
         kernel!("mapping .bss section");
         memory_set.push(
@@ -213,7 +593,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
 
         kernel!("mapping physical memory");
         memory_set.push(
@@ -224,7 +604,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
 
         kernel!("mapping memory-mapped registers");
         for pair in MMIO {
@@ -236,27 +616,37 @@ impl MemorySet {
                     MapPermission::R | MapPermission::W,
                 ),
                 None,
-            );
+            )?;
         }
-        memory_set
+        Ok(memory_set)
     }
 
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new();
-        memory_set.map_trampoline();
+    /// @return (memory set, user stack top, entry point)
+    pub fn from_elf(elf_data: &[u8]) -> MmResult<(Self, usize, usize)> {
+        let mut memory_set = Self::new()?;
+        memory_set.map_trampoline()?;
 
         // mapping program header with U flag
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf"); // magic: ELF
+
+        // PIE is moved by a random number of pages, others stay where
+        // they're linked
+        let pie = elf_header.pt2.type_().as_type() == xmas_elf::header::Type::SharedObject;
+        let bias = if pie {
+            random_pages(ASLR_LOAD_BITS) * PAGE_SIZE
+        } else {
+            0
+        };
         let ph_count = elf_header.pt2.ph_count();
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
             if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let va_start: VirtAddr = (ph.virtual_addr() as usize).into();
-                let va_end: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let va_start: VirtAddr = (bias + ph.virtual_addr() as usize).into();
+                let va_end: VirtAddr = (bias + (ph.virtual_addr() + ph.mem_size()) as usize).into();
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() {
@@ -273,26 +663,37 @@ impl MemorySet {
                 memory_set.push(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                )
+                )?;
             }
         }
 
+        // linker leaves pointers of PIE to relocations, even if it's not moved
+        if pie {
+            memory_set.relocate(&elf, bias)?;
+        }
+
         // mapping user stack with U flag
         let max_end_va: VirtAddr = max_end_vpn.into();
         let mut user_stack_bottom: usize = max_end_va.into();
 
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
+        // guard gap, and a random gap
+        user_stack_bottom += USER_STACK_GUARD + random_pages(ASLR_STACK_BITS) * PAGE_SIZE;
+        let user_stack_top = user_stack_bottom + USER_STACK_LIMIT;
+
+        // only map the top of stack, the rest is mapped when page fault happens
         memory_set.push(
             MapArea::new(
-                user_stack_bottom.into(),
+                (user_stack_top - USER_STACK_SIZE).into(),
                 user_stack_top.into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
+        )?;
+        memory_set.user_stack = Some(VPNRange::new(
+            VirtAddr::from(user_stack_bottom).floor(),
+            VirtAddr::from(user_stack_top).floor(),
+        ));
 
         // map TrapContext
         memory_set.push(
@@ -303,16 +704,45 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
+        memory_set.mmap_base =
+            VirtAddr::from(USER_MMAP_BASE + random_pages(ASLR_MMAP_BITS) * PAGE_SIZE).floor();
 
-        (
+        Ok((
             memory_set,
             user_stack_top,
-            elf.header.pt2.entry_point() as usize,
-        )
+            bias + elf.header.pt2.entry_point() as usize,
+        ))
+    }
+
+    /// # relocate
+    /// apply relocations of PIE loaded `bias` bytes above where it's linked.
+    /// Static PIE only has `R_RISCV_RELATIVE`, others can't be handled
+    /// without a dynamic linker
+    fn relocate(&mut self, elf: &xmas_elf::ElfFile, bias: usize) -> MmResult {
+        const R_RISCV_RELATIVE: u32 = 3;
+        let relas = match elf
+            .find_section_by_name(".rela.dyn")
+            .and_then(|section| section.get_data(elf).ok())
+        {
+            Some(xmas_elf::sections::SectionData::Rela64(relas)) => relas,
+            _ => return Ok(()),
+        };
+        for rela in relas {
+            if rela.get_type() != R_RISCV_RELATIVE {
+                return Err(MmError::InvalidArgument);
+            }
+            let va = VirtAddr::from(bias + rela.get_offset() as usize);
+            let pa = self
+                .page_table
+                .translate_va(va)
+                .ok_or(MmError::InvalidArgument)?;
+            unsafe { *(pa.0 as *mut usize) = bias + rela.get_addend() as usize };
+        }
+        Ok(())
     }
 
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> MmResult {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
@@ -345,6 +775,258 @@ impl MemorySet {
         self.page_table.translate(vpn)
     }
 
+    /// range and permission of every area that user can access
+    pub fn user_areas(&self) -> Vec<(VPNRange, MapPermission)> {
+        self.areas
+            .iter()
+            .filter(|a| a.map_perm.contains(MapPermission::U))
+            .map(|a| (a.vpn_range, a.map_perm))
+            .collect()
+    }
+
+    /// # find_free_area
+    /// lowest `pages` pages from `base` that no area covers
+    fn find_free_area(&self, base: VirtPageNum, pages: usize) -> VirtPageNum {
+        let mut start = base;
+        while let Some(area) = self
+            .areas
+            .iter()
+            .find(|a| a.vpn_range.start().0 < start.0 + pages && start < a.vpn_range.end())
+        {
+            start = area.vpn_range.end();
+        }
+        start
+    }
+
+    /// free range of `pages` pages for shared memory or file mapping
+    fn alloc_mmap_area(&self, pages: usize) -> MmResult<VirtPageNum> {
+        // reject what can never fit first, so page numbers below can't overflow
+        let end = USER_MMAP_END / PAGE_SIZE;
+        if pages > end - self.mmap_base.0 {
+            return Err(MmError::OutOfMemory);
+        }
+        let start = self.find_free_area(self.mmap_base, pages);
+        if start.0 + pages > end {
+            return Err(MmError::OutOfMemory);
+        }
+        Ok(start)
+    }
+
+    /// # attach_shared
+    /// map `segment` readable and writable for user at a free address
+    pub fn attach_shared(&mut self, segment: Arc<ShmSegment>) -> MmResult<VirtAddr> {
+        let start = self.alloc_mmap_area(segment.pages())?;
+        self.push(
+            MapArea::new_shared(
+                start.into(),
+                segment,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        )?;
+        Ok(start.into())
+    }
+
+    /// # detach_shared
+    /// unmap shared area starting at `va`, segment is freed if no one else maps it
+    pub fn detach_shared(&mut self, va: VirtAddr) -> MmResult {
+        if !va.aligned() {
+            return Err(MmError::InvalidArgument);
+        }
+        let vpn = va.floor();
+        let segment = self
+            .areas
+            .iter()
+            .find(|a| a.map_type == MapType::Shared && a.vpn_range.start() == vpn && a.pgoff == 0)
+            .and_then(|a| a.shared.clone())
+            .ok_or(MmError::InvalidArgument)?;
+        // area may be split by `mprotect`, remove all pieces of this attach
+        let end = VirtPageNum(vpn.0 + segment.pages());
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area = &self.areas[idx];
+            let piece = area
+                .shared
+                .as_ref()
+                .map_or(false, |s| Arc::ptr_eq(s, &segment))
+                && area.vpn_range.start() >= vpn
+                && area.vpn_range.end() <= end;
+            if piece {
+                let mut area = self.areas.remove(idx);
+                area.unmap(&mut self.page_table);
+            } else {
+                idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// # mprotect
+    /// change permission of user pages in `[start, end)` to `map_perm`,
+    /// areas are split on the boundaries. Every page must be mapped,
+    /// otherwise nothing is changed and `OutOfMemory` is returned like Linux.
+    /// Shared mapping of a file not opened for write gets `PermissionDenied`
+    /// if it's made writable
+    pub fn mprotect(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        map_perm: MapPermission,
+    ) -> MmResult {
+        if start == end {
+            return Ok(());
+        }
+        let mut cur = start;
+        while cur < end {
+            let area = self
+                .areas
+                .iter()
+                .find(|a| {
+                    a.map_perm.contains(MapPermission::U)
+                        && a.vpn_range.start() <= cur
+                        && cur < a.vpn_range.end()
+                })
+                .ok_or(MmError::OutOfMemory)?;
+            let read_only_file = area
+                .file
+                .as_ref()
+                .map_or(false, |file| file.shared && !file.writable);
+            if read_only_file && map_perm.contains(MapPermission::W) {
+                return Err(MmError::PermissionDenied);
+            }
+            cur = area.vpn_range.end();
+        }
+
+        self.split_areas(start, end);
+        for area in self.areas.iter_mut() {
+            if start <= area.vpn_range.start() && area.vpn_range.end() <= end {
+                area.set_perm(&mut self.page_table, map_perm | MapPermission::U);
+            }
+        }
+        self.merge_areas(start, end);
+        Ok(())
+    }
+
+    /// split areas crossing `start` or `end`, so that every area lies
+    /// either inside or outside of `[start, end)`
+    fn split_areas(&mut self, start: VirtPageNum, end: VirtPageNum) {
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            for at in [start, end] {
+                let area = &mut self.areas[idx];
+                if area.vpn_range.start() < at && at < area.vpn_range.end() {
+                    let tail = area.split_off(at);
+                    self.areas.push(tail);
+                }
+            }
+            idx += 1;
+        }
+    }
+
+    /// merge areas meeting at a page in `[start, end]` if they map contiguous
+    /// memory with the same permission, undoing `split_areas`
+    fn merge_areas(&mut self, start: VirtPageNum, end: VirtPageNum) {
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let next = &self.areas[idx];
+            let at = next.vpn_range.start();
+            let prev = self.areas.iter().position(|a| a.can_merge(next));
+            match prev {
+                Some(prev) if start <= at && at <= end => {
+                    let next = self.areas.remove(idx);
+                    let prev = if prev > idx { prev - 1 } else { prev };
+                    self.areas[prev].merge(next);
+                    // merged area may meet another one
+                    idx = 0;
+                }
+                _ => idx += 1,
+            }
+        }
+    }
+
+    /// # mmap_file
+    /// map `pages` pages of `file` from page `pgoff` at a free address
+    pub fn mmap_file(
+        &mut self,
+        pages: usize,
+        file: FileMapping,
+        pgoff: usize,
+        map_perm: MapPermission,
+    ) -> MmResult<VirtAddr> {
+        let start = self.alloc_mmap_area(pages)?;
+        let end = VirtPageNum(start.0 + pages);
+        self.push(
+            MapArea::new_file(
+                start.into(),
+                end.into(),
+                file,
+                pgoff,
+                map_perm | MapPermission::U,
+            ),
+            None,
+        )?;
+        Ok(start.into())
+    }
+
+    /// # munmap
+    /// unmap file mappings in `[start, end)`, dirty pages of shared mapping
+    /// are written back. Other kinds of area can't be unmapped
+    pub fn munmap(&mut self, start: VirtPageNum, end: VirtPageNum) -> MmResult {
+        let overlap = |a: &MapArea| a.vpn_range.start() < end && start < a.vpn_range.end();
+        if self
+            .areas
+            .iter()
+            .any(|a| overlap(a) && a.map_type != MapType::File)
+        {
+            return Err(MmError::InvalidArgument);
+        }
+
+        self.split_areas(start, end);
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            if overlap(&self.areas[idx]) {
+                let mut area = self.areas.remove(idx);
+                let (start, end) = (area.vpn_range.start(), area.vpn_range.end());
+                area.sync_file(&mut self.page_table, start, end);
+                area.unmap(&mut self.page_table);
+            } else {
+                idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// # msync
+    /// write dirty pages of shared file mappings in `[start, end)` back
+    pub fn msync(&mut self, start: VirtPageNum, end: VirtPageNum) {
+        for area in self.areas.iter_mut() {
+            area.sync_file(&mut self.page_table, start, end);
+        }
+    }
+
+    /// write dirty pages of every shared file mapping back, before the
+    /// address space is dropped by exit or exec
+    pub fn sync_files(&mut self) {
+        for area in self.areas.iter_mut() {
+            let (start, end) = (area.vpn_range.start(), area.vpn_range.end());
+            area.sync_file(&mut self.page_table, start, end);
+        }
+    }
+
+    /// # handle_file_fault
+    /// map page of file mapping at `va` on `access`, which is one of R, W and X.
+    /// @return `None` if `va` isn't in a file mapping
+    pub fn handle_file_fault(&mut self, va: VirtAddr, access: MapPermission) -> Option<MmResult> {
+        let vpn = va.floor();
+        let area = self.areas.iter_mut().find(|a| {
+            a.map_type == MapType::File && a.vpn_range.start() <= vpn && vpn < a.vpn_range.end()
+        })?;
+        if !area.map_perm.contains(access) {
+            return Some(Err(MmError::InvalidArgument));
+        }
+        Some(area.handle_file_fault(&mut self.page_table, vpn, access.contains(MapPermission::W)))
+    }
+
     pub fn remove(&mut self, vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -357,28 +1039,84 @@ impl MemorySet {
         }
     }
 
+    /// # handle_stack_fault
+    /// grow user stack down to the page of `va` if `va` lies in the range
+    /// reserved for stack, report overflow if `va` hits the guard gap below it
+    pub fn handle_stack_fault(&mut self, va: VirtAddr) -> StackFault {
+        let range = match self.user_stack {
+            Some(range) => range,
+            None => return StackFault::NotStack,
+        };
+        let vpn = va.floor();
+        if vpn < range.start() && vpn.0 + USER_STACK_GUARD / PAGE_SIZE >= range.start().0 {
+            return StackFault::Overflow;
+        }
+        if vpn < range.start() || vpn >= range.end() {
+            return StackFault::NotStack;
+        }
+
+        // stack may be split by `mprotect`, the lowest piece grows
+        let area = self
+            .areas
+            .iter_mut()
+            .filter(|a| a.vpn_range.start() >= range.start() && a.vpn_range.end() <= range.end())
+            .min_by_key(|a| a.vpn_range.start())
+            .unwrap();
+        if vpn >= area.vpn_range.start() {
+            // page is mapped already, it must be a permission problem
+            return StackFault::NotStack;
+        }
+        match area.extend_down(&mut self.page_table, vpn) {
+            Ok(()) => StackFault::Grown,
+            Err(_) => StackFault::OutOfMemory,
+        }
+    }
+
+    /// number of frames used by this address space, including page table
+    pub fn frame_count(&self) -> usize {
+        let data: usize = self.areas.iter().map(|a| a.frame_count()).sum();
+        data + self.page_table.frame_count()
+    }
+
+    /// free all pages and page table of an exited process, must not be
+    /// called on the address space in use
     pub fn recycle_pages(&mut self) {
         self.areas.clear();
+        self.user_stack = None;
+        self.page_table.recycle();
     }
 }
 
-impl Clone for MemorySet {
-    fn clone(&self) -> Self {
-        let mut memory_set = MemorySet::new();
-        memory_set.map_trampoline();
+impl MemorySet {
+    /// copy of the whole address space for fork, it can't be `Clone` since
+    /// memory may run out
+    pub fn try_clone(&self) -> MmResult<Self> {
+        let mut memory_set = MemorySet::new()?;
+        memory_set.map_trampoline()?;
 
-        self.areas.iter().for_each(|area| {
+        for area in self.areas.iter() {
+            if area.map_type == MapType::File {
+                let new_area = area.try_clone_file(&mut memory_set.page_table)?;
+                memory_set.areas.push(new_area);
+                continue;
+            }
             // let new_area = MapArea::from_another(area);
             let new_area = area.clone();
-            memory_set.push(new_area, None);
+            memory_set.push(new_area, None)?;
+            // shared area maps the same frames, nothing to copy
+            if area.map_type == MapType::Shared {
+                continue;
+            }
             for vpn in area.vpn_range {
                 let src = self.translate(vpn).unwrap().ppn();
                 let des = memory_set.translate(vpn).unwrap().ppn();
                 des.bytes_array().copy_from_slice(src.bytes_array());
             }
-        });
+        }
+        memory_set.user_stack = self.user_stack;
+        memory_set.mmap_base = self.mmap_base;
 
-        memory_set
+        Ok(memory_set)
     }
 }
 
@@ -1,14 +1,39 @@
 pub mod address;
+mod asid;
+mod aslr;
 pub mod frame_allocator;
-mod heap_allocator;
+pub mod heap_allocator;
 pub mod memory_set;
 pub mod page_table;
+pub mod shm;
+pub mod slab;
+pub mod uaccess;
 
 use self::memory_set::KERNEL_SPACE;
 
+/// errors of memory management, returned to user as errno by syscalls
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmError {
+    /// no physical frame is left
+    OutOfMemory,
+    /// object of the key exists already
+    AlreadyExists,
+    /// no object of the key or address
+    NotFound,
+    /// size or address is not valid
+    InvalidArgument,
+    /// user pointer to memory user can't access
+    BadAddress,
+    /// access isn't allowed by what memory is mapped from
+    PermissionDenied,
+}
+
+pub type MmResult<T = ()> = Result<T, MmError>;
+
 pub fn init() {
     heap_allocator::init();
     frame_allocator::init();
     KERNEL_SPACE.borrow_mut().activate();
+    asid::init();
     debug!("mm:init end");
 }
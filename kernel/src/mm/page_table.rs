@@ -1,11 +1,13 @@
+use alloc::vec;
 use alloc::vec::Vec;
-use alloc::{string::String, vec};
 use bitflags::bitflags;
 
-use super::address::PhysAddr;
+use super::address::{PhysAddr, PAGE_LEVELS};
+use super::asid::{self, Asid};
 use super::{
-    address::{PhysPageNum, StepByOne, VirtAddr, VirtPageNum},
+    address::{PhysPageNum, VirtAddr, VirtPageNum},
     frame_allocator::{frame_alloc, FrameTracker},
+    MmError, MmResult,
 };
 
 bitflags! {
@@ -61,32 +63,133 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+
+    /// valid entry with any of R, W and X maps a page, otherwise it points to
+    /// next level of page table
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid()
+            && self
+                .flags()
+                .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+}
+
+/// # PageSize
+/// size of page mapped by a leaf entry. Walk may stop at any level, so a
+/// leaf one or two levels above the last maps a 2 MiB or 1 GiB page.
+/// 512 GiB page of Sv48 isn't used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    /// number of 4 KiB pages in it
+    pub fn pages(self) -> usize {
+        match self {
+            PageSize::Size4K => 1,
+            PageSize::Size2M => 1 << 9,
+            PageSize::Size1G => 1 << 18,
+        }
+    }
+
+    /// index of level where the leaf lives, in the order of `VirtPageNum::indexes`
+    fn level(self) -> usize {
+        match self {
+            PageSize::Size4K => PAGE_LEVELS - 1,
+            PageSize::Size2M => PAGE_LEVELS - 2,
+            PageSize::Size1G => PAGE_LEVELS - 3,
+        }
+    }
+
+    /// size of leaf in `level`, `None` if no leaf is allowed there
+    fn at_level(level: usize) -> Option<Self> {
+        [PageSize::Size4K, PageSize::Size2M, PageSize::Size1G]
+            .into_iter()
+            .find(|size| size.level() == level)
+    }
+
+    /// largest page that starts at both `vpn` and `ppn` and fits in `pages`
+    pub fn fit(vpn: VirtPageNum, ppn: PhysPageNum, pages: usize) -> Self {
+        [PageSize::Size1G, PageSize::Size2M]
+            .into_iter()
+            .find(|size| {
+                let n = size.pages();
+                vpn.0 % n == 0 && ppn.0 % n == 0 && pages >= n
+            })
+            .unwrap_or(PageSize::Size4K)
+    }
 }
 
+/// MODE field of satp
+#[cfg(not(feature = "sv48"))]
+const SATP_MODE: usize = 8;
+#[cfg(feature = "sv48")]
+const SATP_MODE: usize = 9;
+
 pub struct PageTable {
     root_ppn: PhysPageNum,
     frames: Vec<FrameTracker>,
+    asid: Asid,
 }
 
 impl PageTable {
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        Self {
+    pub fn new() -> MmResult<Self> {
+        let frame = frame_alloc().ok_or(MmError::OutOfMemory)?;
+        Ok(Self {
             root_ppn: frame.ppn,
             frames: vec![frame],
-        }
+            asid: Asid::NONE,
+        })
+    }
+
+    /// page table of kernel space, it always has ASID 0
+    pub fn new_kernel() -> MmResult<Self> {
+        let mut page_table = Self::new()?;
+        page_table.asid = Asid::KERNEL;
+        Ok(page_table)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
     }
 
+    pub fn root_ppn(&self) -> PhysPageNum {
+        self.root_ppn
+    }
+
+    /// free every frame but root and clear root, the table maps nothing after it
+    pub fn recycle(&mut self) {
+        self.frames.truncate(1);
+        self.root_ppn
+            .pte_array()
+            .iter_mut()
+            .for_each(|pte| *pte = PageTableEntry::empty());
+    }
+
+    /// @return `None` if there is no memory for page table
     pub fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_pte_create_at(vpn, PageSize::Size4K)
+    }
+
+    /// entry of `vpn` in the level where leaf of `size` lives
+    fn find_pte_create_at(
+        &mut self,
+        vpn: VirtPageNum,
+        size: PageSize,
+    ) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         for (i, &idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.pte_array()[idx];
-            if i == 2 {
+            if i == size.level() {
                 return Some(pte);
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is in a huge page", vpn);
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
@@ -95,13 +198,23 @@ impl PageTable {
         None
     }
 
+    /// entry of 4 KiB page `vpn`, `None` if it lies in a huge page
     pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_leaf(vpn)
+            .and_then(|(pte, size)| (size == PageSize::Size4K).then_some(pte))
+    }
+
+    /// # find_leaf
+    /// entry mapping `vpn` and size of its page, walk stops at the first
+    /// leaf. The entry of last level is returned even if it's invalid
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, PageSize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         for (i, &idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.pte_array()[idx];
-            if i == 2 {
-                return Some(pte);
+            if i == PAGE_LEVELS - 1 || pte.is_leaf() {
+                let size = PageSize::at_level(i).expect("leaf in unsupported level");
+                return Some((pte, size));
             }
             if !pte.is_valid() {
                 return None;
@@ -111,34 +224,103 @@ impl PageTable {
         None
     }
 
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> MmResult {
+        let pte = self.find_pte_create(vpn).ok_or(MmError::OutOfMemory)?;
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush(vpn);
+        Ok(())
+    }
+
+    /// change flags of a mapped page
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before set flags", vpn);
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+        self.flush(vpn);
+    }
+
+    /// # map_huge
+    /// map a page of `size` at `vpn` to `ppn`, both must be aligned to it
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+        size: PageSize,
+    ) -> MmResult {
+        assert!(
+            vpn.0 % size.pages() == 0 && ppn.0 % size.pages() == 0,
+            "{:?} page at {:?} -> {:?} isn't aligned",
+            size,
+            vpn,
+            ppn
+        );
+        // a leaf never has no permission, or it would be taken as next level
+        assert!(flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X));
+        let pte = self
+            .find_pte_create_at(vpn, size)
+            .ok_or(MmError::OutOfMemory)?;
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush(vpn);
+        Ok(())
     }
+
+    /// unmap page of `size` at `vpn`, the table it was in is kept
+    pub fn unmap_huge(&mut self, vpn: VirtPageNum, size: PageSize) {
+        let (pte, found) = self.find_leaf(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before unmap", vpn);
+        assert_eq!(found, size, "vpn {:?} is mapped by another size", vpn);
+        *pte = PageTableEntry::empty();
+        self.flush(vpn);
+    }
+
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         let pte = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmap", vpn);
         *pte = PageTableEntry::empty();
+        self.flush(vpn);
     }
 
     pub fn from_token(satp: usize) -> Self {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1 << 44) - 1)),
             frames: Vec::new(),
+            asid: Asid::NONE,
         }
     }
 
+    /// entry of `vpn`, for page in huge page, entry of its 4 KiB part is made up
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(|pte| *pte)
+        self.find_leaf(vpn).map(|(pte, size)| {
+            if size == PageSize::Size4K {
+                *pte
+            } else {
+                let offset = vpn.0 % size.pages();
+                PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+            }
+        })
     }
 
+    /// satp of this page table, ASID in it may be out of date, so only
+    /// `fresh_token` may be written to satp for user space
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        SATP_MODE << 60 | self.asid.satp_bits() | self.root_ppn.0
+    }
+
+    /// token with an ASID of current generation, for switching to it
+    pub fn fresh_token(&mut self) -> usize {
+        asid::refresh(&mut self.asid);
+        self.token()
+    }
+
+    fn flush(&self, vpn: VirtPageNum) {
+        asid::flush_page(self.asid, VirtAddr::from(vpn).0);
     }
 
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor()).map(|pte| {
+        self.translate(va.clone().floor()).map(|pte| {
             let aligned_pa: PhysAddr = pte.ppn().into();
             let offset = va.offset();
             let addr = usize::from(aligned_pa) + offset;
@@ -147,57 +329,6 @@ impl PageTable {
     }
 }
 
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let va_start = VirtAddr::from(start);
-        let mut vpn = va_start.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
-        vpn.step();
-        let mut va_end: VirtAddr = vpn.into();
-        va_end = va_end.min(VirtAddr::from(end));
-        if va_end.offset() == 0 {
-            v.push(&mut ppn.bytes_array()[va_start.offset()..]);
-        } else {
-            v.push(&mut ppn.bytes_array()[va_start.offset()..va_end.offset()]);
-        }
-        start = va_end.into();
-    }
-    v
-}
-
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let mut res = String::new();
-    loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(start))
-            .unwrap()
-            .as_mut());
-
-        // get '\0', the end of string
-        if ch == 0 {
-            break;
-        } else {
-            res.push(ch as char);
-            start += 1;
-        }
-    }
-
-    res
-}
-
-pub fn translated_refmut<T>(token: usize, ptr: *const T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-
-    page_table.translate_va(va.into()).unwrap().as_mut()
-}
-
 pub struct UserBuf {
     pub buffers: Vec<&'static mut [u8]>,
 }
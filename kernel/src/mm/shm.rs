@@ -0,0 +1,86 @@
+//! # Shared memory
+//!
+//! A segment is a group of frames found by a key. Every process attaching
+//! it maps the same frames as a `MapType::Shared` area, fork keeps the
+//! mapping shared instead of copying it.
+//!
+//! Segments are reference counted by the areas mapping them: the key table
+//! only keeps `Weak`, so frames are freed once the last process detaches or
+//! exits, and the key can be created again.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+
+use crate::sync::UniProcSafeCell;
+
+use super::{
+    address::PhysPageNum,
+    frame_allocator::{frame_alloc, FrameTracker},
+    MmError, MmResult,
+};
+
+pub struct ShmSegment {
+    key: usize,
+    frames: Vec<FrameTracker>,
+}
+
+impl ShmSegment {
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    /// number of pages in segment
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// frame of the `idx`th page
+    pub fn ppn(&self, idx: usize) -> PhysPageNum {
+        self.frames[idx].ppn
+    }
+}
+
+lazy_static! {
+    static ref SHM_TABLE: UniProcSafeCell<BTreeMap<usize, Weak<ShmSegment>>> =
+        UniProcSafeCell::new(BTreeMap::new());
+}
+
+/// # create
+/// create a zeroed segment of `pages` pages for `key`, which must not be
+/// attached by anyone now
+pub fn create(key: usize, pages: usize) -> MmResult<Arc<ShmSegment>> {
+    if pages == 0 {
+        return Err(MmError::InvalidArgument);
+    }
+    let mut table = SHM_TABLE.borrow_mut();
+    // forget segments that are gone
+    table.retain(|_, segment| segment.strong_count() > 0);
+    if table.contains_key(&key) {
+        return Err(MmError::AlreadyExists);
+    }
+    drop(table);
+
+    // frames allocated so far are freed by drop if it fails
+    let mut frames = Vec::new();
+    for _ in 0..pages {
+        frames.push(frame_alloc().ok_or(MmError::OutOfMemory)?);
+    }
+    let segment = Arc::new(ShmSegment { key, frames });
+    SHM_TABLE.borrow_mut().insert(key, Arc::downgrade(&segment));
+    debug!("shm: create key {:#x}, {} pages", key, pages);
+    Ok(segment)
+}
+
+/// # get
+/// segment of `key` that is still attached by some process
+pub fn get(key: usize) -> MmResult<Arc<ShmSegment>> {
+    SHM_TABLE
+        .borrow_mut()
+        .get(&key)
+        .and_then(|segment| segment.upgrade())
+        .ok_or(MmError::NotFound)
+}
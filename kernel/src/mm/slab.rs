@@ -0,0 +1,298 @@
+//! # Slab allocator
+//!
+//! Objects of one size are cut from single frames, called slabs, instead of
+//! going through the buddy system of kernel heap. Kernel objects allocated
+//! and freed all the time get a cache of their own in `SLAB_CACHES`, and the
+//! global allocator sends every allocation of exactly their layout there, so
+//! `Arc::new` needs no change. Types of the same layout share a cache. When
+//! a cache can't get a frame, the allocation falls back to kernel heap, which
+//! tells its own objects from slab objects by address when they're freed.
+//!
+//! A slab starts with `Slab`, followed by a stack of indexes of its free
+//! objects, then the objects. Slabs with free objects are kept in a doubly
+//! linked list, full ones are found from the address of an object when it's
+//! freed. Empty slabs go back to frame allocator, except the last one of a
+//! cache.
+
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+use easy_fs::{block_cache::BlockCache, vfs::Inode};
+use spin::Mutex;
+
+use super::{
+    address::{PhysAddr, PhysPageNum},
+    frame_allocator::{frames_try_alloc, frames_try_dealloc},
+};
+use crate::{config::PAGE_SIZE, fs::page_cache::FilePage, task::task::ProcessControlBlock};
+
+/// head of a slab, at the start of its frame
+#[repr(C)]
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    /// number of indexes in free stack
+    free: usize,
+}
+
+impl Slab {
+    /// stack of indexes of free objects, right after head
+    fn free_stack(&mut self) -> *mut u16 {
+        unsafe { (self as *mut Slab).add(1) as *mut u16 }
+    }
+}
+
+/// statistics of a slab cache
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    /// objects in use
+    pub active: usize,
+    /// objects in all slabs
+    pub total: usize,
+    pub slabs: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+struct SlabList {
+    /// slabs with free objects
+    partial: *mut Slab,
+    stats: SlabStats,
+}
+
+// slabs are only touched with the lock held
+unsafe impl Send for SlabList {}
+
+/// # SlabCache
+/// cache of objects of one layout
+pub struct SlabCache {
+    /// size asked by user, allocation of other size never comes here
+    size: usize,
+    align: usize,
+    /// size of each object in slab
+    stride: usize,
+    /// objects in a slab
+    capacity: usize,
+    /// offset of first object in slab
+    first: usize,
+    list: Mutex<SlabList>,
+}
+
+const fn align_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
+impl SlabCache {
+    pub const fn new(name: &'static str, layout: Layout) -> Self {
+        let align = layout.align();
+        let stride = align_up(layout.size(), align);
+        let head = size_of::<Slab>();
+        let mut capacity = (PAGE_SIZE - head) / (stride + size_of::<u16>());
+        while align_up(head + capacity * size_of::<u16>(), align) + capacity * stride > PAGE_SIZE {
+            capacity -= 1;
+        }
+        assert!(capacity >= 4, "object is too large for slab");
+        Self {
+            size: layout.size(),
+            align,
+            stride,
+            capacity,
+            first: align_up(head + capacity * size_of::<u16>(), align),
+            list: Mutex::new(SlabList {
+                partial: null_mut(),
+                stats: SlabStats {
+                    name,
+                    object_size: stride,
+                    active: 0,
+                    total: 0,
+                    slabs: 0,
+                    allocs: 0,
+                    frees: 0,
+                },
+            }),
+        }
+    }
+
+    /// if allocation of `layout` is served by this cache
+    fn fits(&self, layout: Layout) -> bool {
+        layout.size() == self.size && layout.align() <= self.align
+    }
+
+    /// # alloc
+    /// @return `None` if a new slab is needed but frame allocator has no frame
+    /// or is in use
+    pub fn alloc(&self) -> Option<NonNull<u8>> {
+        let mut list = self.list.lock();
+        if list.partial.is_null() {
+            let slab = self.new_slab()?;
+            list.partial = slab;
+            list.stats.slabs += 1;
+            list.stats.total += self.capacity;
+        }
+        let slab = unsafe { &mut *list.partial };
+        slab.free -= 1;
+        let idx = unsafe { *slab.free_stack().add(slab.free) } as usize;
+        if slab.free == 0 {
+            // full slab leaves the list
+            unlink(&mut list, slab);
+        }
+        list.stats.active += 1;
+        list.stats.allocs += 1;
+        NonNull::new((slab as *mut Slab as usize + self.first + idx * self.stride) as *mut u8)
+    }
+
+    /// # dealloc
+    /// `ptr` must come from `alloc` of this cache
+    pub fn dealloc(&self, ptr: NonNull<u8>) {
+        let addr = ptr.as_ptr() as usize;
+        let slab = unsafe { &mut *((addr & !(PAGE_SIZE - 1)) as *mut Slab) };
+        let offset = addr - slab as *mut Slab as usize - self.first;
+        assert!(
+            offset % self.stride == 0 && offset / self.stride < self.capacity,
+            "{:#x} isn't an object of slab",
+            addr
+        );
+
+        let mut list = self.list.lock();
+        if slab.free == 0 {
+            // full slab gets a free object
+            link(&mut list, slab);
+        }
+        unsafe { *slab.free_stack().add(slab.free) = (offset / self.stride) as u16 };
+        slab.free += 1;
+        list.stats.active -= 1;
+        list.stats.frees += 1;
+
+        // keep the last slab to avoid allocating a frame again soon
+        let only = slab.prev.is_null() && slab.next.is_null();
+        if slab.free == self.capacity && !only {
+            unlink(&mut list, slab);
+            let ppn = PhysAddr::from(slab as *mut Slab as usize).floor();
+            if frames_try_dealloc(ppn, 0) {
+                list.stats.slabs -= 1;
+                list.stats.total -= self.capacity;
+            } else {
+                link(&mut list, slab);
+            }
+        }
+    }
+
+    /// a slab whose objects are all free
+    fn new_slab(&self) -> Option<*mut Slab> {
+        let ppn: PhysPageNum = frames_try_alloc(0)?;
+        let slab = PhysAddr::from(ppn).0 as *mut Slab;
+        unsafe {
+            *slab = Slab {
+                prev: null_mut(),
+                next: null_mut(),
+                free: self.capacity,
+            };
+            // objects are handed out in order of address
+            let stack = (*slab).free_stack();
+            for i in 0..self.capacity {
+                *stack.add(i) = (self.capacity - 1 - i) as u16;
+            }
+        }
+        Some(slab)
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.list.lock().stats
+    }
+}
+
+impl Drop for SlabCache {
+    fn drop(&mut self) {
+        let list = self.list.get_mut();
+        assert_eq!(
+            list.stats.active, 0,
+            "slab cache {} is in use",
+            list.stats.name
+        );
+        while !list.partial.is_null() {
+            let slab = list.partial;
+            list.partial = unsafe { (*slab).next };
+            frames_try_dealloc(PhysAddr::from(slab as usize).floor(), 0);
+        }
+    }
+}
+
+/// put `slab` at the head of partial list
+fn link(list: &mut SlabList, slab: &mut Slab) {
+    slab.prev = null_mut();
+    slab.next = list.partial;
+    if !list.partial.is_null() {
+        unsafe { (*list.partial).prev = slab };
+    }
+    list.partial = slab;
+}
+
+fn unlink(list: &mut SlabList, slab: &mut Slab) {
+    if slab.prev.is_null() {
+        list.partial = slab.next;
+    } else {
+        unsafe { (*slab.prev).next = slab.next };
+    }
+    if !slab.next.is_null() {
+        unsafe { (*slab.next).prev = slab.prev };
+    }
+    slab.prev = null_mut();
+    slab.next = null_mut();
+}
+
+/// layout of what `Arc::new` allocates for `T`: two counters, then `T`
+pub const fn arc_layout<T>() -> Layout {
+    let layout = Layout::new::<T>();
+    let align = if layout.align() > size_of::<usize>() {
+        layout.align()
+    } else {
+        size_of::<usize>()
+    };
+    let size = align_up(2 * size_of::<usize>(), layout.align()) + layout.size();
+    unsafe { Layout::from_size_align_unchecked(align_up(size, align), align) }
+}
+
+/// caches of hot kernel objects, they must exist before any allocation, or
+/// object allocated by heap might be freed to slab
+static SLAB_CACHES: [SlabCache; 4] = [
+    SlabCache::new("process", arc_layout::<ProcessControlBlock>()),
+    SlabCache::new("inode", arc_layout::<Inode>()),
+    SlabCache::new("block cache", arc_layout::<Mutex<BlockCache>>()),
+    SlabCache::new("file page", arc_layout::<FilePage>()),
+];
+
+fn cache_of(layout: Layout) -> Option<&'static SlabCache> {
+    SLAB_CACHES.iter().find(|cache| cache.fits(layout))
+}
+
+/// # alloc
+/// allocate `layout` from its slab cache.
+///
+/// @return `None` if no cache is for `layout` or cache can't get a frame,
+/// heap should serve it then
+pub fn alloc(layout: Layout) -> Option<*mut u8> {
+    cache_of(layout)?.alloc().map(|ptr| ptr.as_ptr())
+}
+
+/// # dealloc
+/// `ptr` must not come from heap
+///
+/// @return false if no cache is for `layout`
+pub fn dealloc(ptr: *mut u8, layout: Layout) -> bool {
+    match (cache_of(layout), NonNull::new(ptr)) {
+        (Some(cache), Some(ptr)) => {
+            cache.dealloc(ptr);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn slab_stats() -> Vec<SlabStats> {
+    SLAB_CACHES.iter().map(|cache| cache.stats()).collect()
+}
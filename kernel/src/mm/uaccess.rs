@@ -0,0 +1,268 @@
+//! # User memory access
+//!
+//! Syscalls reach user memory through these functions only, a bad pointer
+//! becomes `MmError::BadAddress`, i.e. `EFAULT`, instead of a kernel panic.
+//!
+//! Kernel runs in its own address space, where user memory is not mapped.
+//! To copy from or to user, `UserWindow` copies root entries of user page
+//! table to upper half of kernel page table, so user address `va` is at
+//! `USER_ALIAS + va`, and sets `sstatus.SUM` to let kernel touch user pages.
+//! Only `__copy_user` and `__strncpy_user` of `uaccess.S` touch them, a page
+//! fault of theirs is resumed at its fixup by `kernel_trap_handler` through
+//! exception table, and they return an error.
+//!
+//! Buffers for `File` may be used after the task sleeps, when another address
+//! space is in the window, so `user_buffer` checks every page in the page
+//! table of user and hands out pages through the direct map of frames.
+//!
+//! Pages that are mapped on first access, i.e. file mappings and user stack
+//! below its mapped part, are mapped before they're touched.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    arch::global_asm,
+    mem::{size_of, MaybeUninit},
+};
+use riscv::register::{satp, sstatus};
+
+use super::{
+    address::{PhysPageNum, VirtAddr},
+    asid,
+    memory_set::MapPermission,
+    page_table::{PTEFlags, PageTable, PageTableEntry, UserBuf},
+    MmError, MmResult,
+};
+use crate::{
+    config::{eex_table, sex_table, PAGE_SIZE, USER_ALIAS, USER_MMAP_END},
+    task::processor::cur_task,
+};
+
+global_asm!(include_str!("uaccess.S"));
+
+extern "C" {
+    fn __copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn __strncpy_user(dst: *mut u8, src: *const u8, max: usize) -> isize;
+}
+
+/// entry of exception table
+#[repr(C)]
+struct ExTableEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+/// # search_exception_table
+/// where to continue if instruction at `pc` faults, `None` if it's not
+/// allowed to fault
+pub fn search_exception_table(pc: usize) -> Option<usize> {
+    let len = (eex_table as usize - sex_table as usize) / size_of::<ExTableEntry>();
+    let table =
+        unsafe { core::slice::from_raw_parts(sex_table as usize as *const ExTableEntry, len) };
+    table
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
+
+/// index of root entry mapping `USER_ALIAS`
+const ALIAS_ROOT: usize = 256;
+/// root entries of user space, the last one of lower half is left out
+const USER_ROOTS: usize = ALIAS_ROOT - 1;
+
+/// # UserWindow
+/// user space of `token` is at `USER_ALIAS` in kernel space until it's
+/// dropped. It must not live across task switch.
+struct UserWindow {
+    kernel_root: &'static mut [PageTableEntry],
+}
+
+impl UserWindow {
+    fn open(token: usize) -> Self {
+        let user_root = PageTable::from_token(token).root_ppn().pte_array();
+        let kernel_root = PageTable::from_token(satp::read().bits())
+            .root_ppn()
+            .pte_array();
+        kernel_root[ALIAS_ROOT..ALIAS_ROOT + USER_ROOTS].copy_from_slice(&user_root[..USER_ROOTS]);
+        asid::flush_kernel();
+        unsafe { sstatus::set_sum() };
+        Self { kernel_root }
+    }
+}
+
+impl Drop for UserWindow {
+    fn drop(&mut self) {
+        unsafe { sstatus::clear_sum() };
+        self.kernel_root[ALIAS_ROOT..ALIAS_ROOT + USER_ROOTS]
+            .iter_mut()
+            .for_each(|pte| *pte = PageTableEntry::empty());
+        asid::flush_kernel();
+    }
+}
+
+/// # fault_in
+/// map page at `va` as if user has read it, or written it when `write` is
+/// set. A read leaves private file page shared and shared one clean.
+fn fault_in(page_table: &PageTable, va: VirtAddr, write: bool) {
+    if page_table.translate(va.floor()).map_or(false, |pte| {
+        pte.is_valid()
+            && if write {
+                pte.writable()
+            } else {
+                pte.readable()
+            }
+    }) {
+        return;
+    }
+    let task = match cur_task() {
+        Some(task) => task,
+        None => return,
+    };
+    // callers must not hold it, or lazily mapped pages look unmapped
+    let inner = task.inner.try_borrow_mut();
+    if let Some(mut inner) = inner {
+        let memory_set = &mut inner.memory_set;
+        let access = if write {
+            MapPermission::W
+        } else {
+            MapPermission::R
+        };
+        if memory_set.handle_file_fault(va, access).is_none() {
+            // it's checked later whether the stack has grown
+            memory_set.handle_stack_fault(va);
+        }
+    }
+}
+
+/// frame of user page at `va`, if user may write it when `write` is set, or
+/// read it otherwise
+fn user_page(page_table: &PageTable, va: VirtAddr, write: bool) -> MmResult<PhysPageNum> {
+    fault_in(page_table, va, write);
+    page_table
+        .translate(va.floor())
+        .filter(|pte| {
+            pte.is_valid()
+                && pte.flags().contains(PTEFlags::U)
+                && if write {
+                    pte.writable()
+                } else {
+                    pte.readable()
+                }
+        })
+        .map(|pte| pte.ppn())
+        .ok_or(MmError::BadAddress)
+}
+
+/// # user_slices
+/// pieces of user range `[ptr, ptr + len)` in each page, in order
+fn user_slices(
+    token: usize,
+    ptr: usize,
+    len: usize,
+    write: bool,
+) -> MmResult<Vec<&'static mut [u8]>> {
+    // a pointer into upper half would alias a user address after it's cut
+    // to the width of virtual address
+    let end = ptr.checked_add(len).ok_or(MmError::BadAddress)?;
+    if end > USER_MMAP_END {
+        return Err(MmError::BadAddress);
+    }
+    let page_table = PageTable::from_token(token);
+    let mut slices = Vec::new();
+    let mut start = ptr;
+    while start < end {
+        let va = VirtAddr::from(start);
+        let ppn = user_page(&page_table, va, write)?;
+        let len = (PAGE_SIZE - va.offset()).min(end - start);
+        slices.push(&mut ppn.bytes_array()[va.offset()..va.offset() + len]);
+        start += len;
+    }
+    Ok(slices)
+}
+
+/// user buffer for `File::read` if `write` is set, or `File::write`
+pub fn user_buffer(token: usize, ptr: *const u8, len: usize, write: bool) -> MmResult<UserBuf> {
+    user_slices(token, ptr as usize, len, write).map(UserBuf::new)
+}
+
+/// # prepare
+/// check that `[ptr, ptr + len)` is in user space, and map its pages that
+/// are mapped on first access
+fn prepare(token: usize, ptr: usize, len: usize, write: bool) -> MmResult {
+    let end = ptr.checked_add(len).ok_or(MmError::BadAddress)?;
+    if end > USER_MMAP_END {
+        return Err(MmError::BadAddress);
+    }
+    let page_table = PageTable::from_token(token);
+    let mut page = ptr & !(PAGE_SIZE - 1);
+    while page < end {
+        fault_in(&page_table, page.into(), write);
+        page += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+pub fn copy_from_user(token: usize, dst: &mut [u8], src: *const u8) -> MmResult {
+    prepare(token, src as usize, dst.len(), false)?;
+    let src = (USER_ALIAS + src as usize) as *const u8;
+    let _window = UserWindow::open(token);
+    match unsafe { __copy_user(dst.as_mut_ptr(), src, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(MmError::BadAddress),
+    }
+}
+
+/// bytes before the first page can't be written may have been copied
+pub fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) -> MmResult {
+    prepare(token, dst as usize, src.len(), true)?;
+    let dst = (USER_ALIAS + dst as usize) as *mut u8;
+    let _window = UserWindow::open(token);
+    match unsafe { __copy_user(dst, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(MmError::BadAddress),
+    }
+}
+
+/// # strncpy_from_user
+/// string ending with '\0' at `src`, `InvalidArgument` if it's not found
+/// in `max` bytes
+pub fn strncpy_from_user(token: usize, src: *const u8, max: usize) -> MmResult<String> {
+    let mut bytes = Vec::new();
+    let mut ptr = src as usize;
+    // string is copied page by page, its length is unknown
+    while bytes.len() < max {
+        let len = (PAGE_SIZE - VirtAddr::from(ptr).offset()).min(max - bytes.len());
+        prepare(token, ptr, 1, false)?;
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        let copied = {
+            let _window = UserWindow::open(token);
+            let src = (USER_ALIAS + ptr) as *const u8;
+            unsafe { __strncpy_user(bytes[start..].as_mut_ptr(), src, len) }
+        };
+        if copied < 0 {
+            return Err(MmError::BadAddress);
+        }
+        if (copied as usize) < len {
+            bytes.truncate(start + copied as usize);
+            return String::from_utf8(bytes).map_err(|_| MmError::InvalidArgument);
+        }
+        ptr += len;
+    }
+    Err(MmError::InvalidArgument)
+}
+
+/// read a `T` from user, which may be unaligned
+pub fn get_user<T: Copy>(token: usize, src: *const T) -> MmResult<T> {
+    let mut val = MaybeUninit::<T>::uninit();
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(token, bytes, src as *const u8)?;
+    Ok(unsafe { val.assume_init() })
+}
+
+/// write `val` to user, `dst` may be unaligned
+pub fn put_user<T: Copy>(token: usize, dst: *mut T, val: T) -> MmResult {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(&val as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(token, dst as *mut u8, bytes)
+}
@@ -0,0 +1,160 @@
+//! # Sampling profiler
+//!
+//! Timer samples periodically while any profile is running, and records the
+//! interrupted pc into histogram of the running task, if it is profiled.
+//! Histograms are keyed by pid, so a profile can still be read after its
+//! task exits, until it is released or the pid is allocated again.
+//!
+//! Samples are taken in interrupt handler, so nothing here may allocate or
+//! touch `RefCell`, and `PROFILES` is only locked with interrupts disabled.
+
+use crate::task::processor::running_pid;
+use crate::timer;
+use crate::trap::{disable_kernel_interrupt, restore_kernel_interrupt};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use riscv::register::{
+    sepc,
+    sstatus::{self, SPP},
+};
+use spin::Mutex;
+
+/// different pc can be recorded in one histogram
+const HISTOGRAM_SIZE: usize = 1024;
+/// tasks can be profiled at the same time
+const MAX_PROFILES: usize = 8;
+
+pub const MODE_USER: u32 = 0;
+pub const MODE_KERNEL: u32 = 1;
+
+/// one bucket of histogram, also the record copied to user
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Sample {
+    pub pc: usize,
+    pub count: u32,
+    pub mode: u32,
+}
+
+struct Profile {
+    pid: usize,
+    running: bool,
+    /// open addressing hash table by (pc, mode), empty if count == 0
+    samples: Box<[Sample]>,
+    /// samples lost because histogram is full
+    dropped: usize,
+}
+
+impl Profile {
+    fn record(&mut self, pc: usize, mode: u32) {
+        let start = (pc >> 1) % HISTOGRAM_SIZE;
+        for i in 0..HISTOGRAM_SIZE {
+            let sample = &mut self.samples[(start + i) % HISTOGRAM_SIZE];
+            if sample.count == 0 {
+                *sample = Sample { pc, count: 1, mode };
+                return;
+            }
+            if sample.pc == pc && sample.mode == mode {
+                sample.count += 1;
+                return;
+            }
+        }
+        self.dropped += 1;
+    }
+}
+
+lazy_static! {
+    static ref PROFILES: Mutex<Vec<Profile>> = Mutex::new(Vec::with_capacity(MAX_PROFILES));
+}
+
+/// run `f` with `PROFILES` locked, interrupts must be disabled while holding it
+fn with_profiles<T>(f: impl FnOnce(&mut Vec<Profile>) -> T) -> T {
+    let sie = disable_kernel_interrupt();
+    let res = f(&mut PROFILES.lock());
+    restore_kernel_interrupt(sie);
+    res
+}
+
+/// # sample
+/// called by timer interrupt handler, sepc and sstatus still hold the state
+/// of interrupted code
+pub fn sample() {
+    let pid = match running_pid() {
+        Some(pid) => pid,
+        None => return,
+    };
+    let mode = match sstatus::read().spp() {
+        SPP::User => MODE_USER,
+        SPP::Supervisor => MODE_KERNEL,
+    };
+    let pc = sepc::read();
+    with_profiles(|profiles| {
+        if let Some(profile) = profiles.iter_mut().find(|p| p.pid == pid && p.running) {
+            profile.record(pc, mode);
+        }
+    })
+}
+
+/// start profiling `pid`, samples of last profile are kept if it isn't released
+///
+/// @return false if too many tasks are being profiled
+pub fn start(pid: usize) -> bool {
+    // histogram is too large for kernel stack, and can't be allocated with
+    // `PROFILES` locked, it's dropped after unlocking if not used
+    let mut samples = Some(vec![Sample::default(); HISTOGRAM_SIZE].into_boxed_slice());
+    let started = with_profiles(|profiles| {
+        if let Some(profile) = profiles.iter_mut().find(|p| p.pid == pid) {
+            profile.running = true;
+        } else if profiles.len() < MAX_PROFILES {
+            profiles.push(Profile {
+                pid,
+                running: true,
+                samples: samples.take().unwrap(),
+                dropped: 0,
+            });
+        } else {
+            return false;
+        }
+        true
+    });
+    if started {
+        timer::start_sampling();
+    }
+    started
+}
+
+/// stop profiling `pid`, samples are kept for `read` unless `release`
+///
+/// @return false if `pid` isn't profiled
+pub fn stop(pid: usize, release: bool) -> bool {
+    // released profile is dropped after unlocking
+    let (stopped, released, running) = with_profiles(|profiles| {
+        let (stopped, released) = match profiles.iter().position(|p| p.pid == pid) {
+            Some(idx) if release => (true, Some(profiles.swap_remove(idx))),
+            Some(idx) => {
+                profiles[idx].running = false;
+                (true, None)
+            }
+            None => (false, None),
+        };
+        (stopped, released, profiles.iter().any(|p| p.running))
+    });
+    drop(released);
+    if !running {
+        timer::stop_sampling();
+    }
+    stopped
+}
+
+/// @return (samples, dropped) of `pid`, samples are sorted by count
+pub fn read(pid: usize) -> Option<(Vec<Sample>, usize)> {
+    let mut samples: Vec<Sample> = Vec::with_capacity(HISTOGRAM_SIZE);
+    let dropped = with_profiles(|profiles| {
+        let profile = profiles.iter().find(|p| p.pid == pid)?;
+        samples.extend(profile.samples.iter().filter(|s| s.count != 0));
+        Some(profile.dropped)
+    })?;
+    samples.sort_unstable_by(|a, b| b.count.cmp(&a.count));
+    Some((samples, dropped))
+}
@@ -16,4 +16,9 @@ impl<T> UniProcSafeCell<T> {
     pub fn borrow_mut(&self) -> RefMut<'_, T> {
         self.inner.borrow_mut()
     }
+
+    /// `None` if it's borrowed already
+    pub fn try_borrow_mut(&self) -> Option<RefMut<'_, T>> {
+        self.inner.try_borrow_mut().ok()
+    }
 }
@@ -0,0 +1,25 @@
+//! error numbers, same as Linux. Syscalls return them as negative values
+use crate::mm::MmError;
+
+pub const ENOENT: isize = 2;
+pub const EBADF: isize = 9;
+pub const ENOMEM: isize = 12;
+pub const EACCES: isize = 13;
+pub const EFAULT: isize = 14;
+pub const EEXIST: isize = 17;
+pub const ENODEV: isize = 19;
+pub const EINVAL: isize = 22;
+
+impl MmError {
+    /// negative errno returned to user
+    pub fn errno(self) -> isize {
+        match self {
+            MmError::OutOfMemory => -ENOMEM,
+            MmError::AlreadyExists => -EEXIST,
+            MmError::NotFound => -ENOENT,
+            MmError::InvalidArgument => -EINVAL,
+            MmError::BadAddress => -EFAULT,
+            MmError::PermissionDenied => -EACCES,
+        }
+    }
+}
@@ -4,11 +4,13 @@
 pub const FD_STDIN: usize = 0;
 pub const FD_STDOUT: usize = 1;
 pub const FD_STDERR: usize = 2;
+/// longest path taken from user, including '\0'
+pub const PATH_MAX: usize = 4096;
 
 use core::borrow::BorrowMut;
 
 use crate::fs::inode::{self, OpenFlags};
-use crate::mm::page_table::{translated_byte_buffer, translated_str, UserBuf};
+use crate::mm::uaccess::{strncpy_from_user, user_buffer};
 use crate::sbi::consolo_getchar;
 use crate::task::processor::{self, cur_task, cur_user_token};
 use crate::task::suspend_cur_and_run_next;
@@ -25,7 +27,12 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     if let Some(file) = &inner.fd_table[fd] {
         let file = file.clone();
         drop(inner);
-        file.write(UserBuf::new(translated_byte_buffer(token, buf, len))) as isize
+        // it may sleep, don't keep task alive on kernel stack
+        drop(task);
+        match user_buffer(token, buf, len, false) {
+            Ok(buf) => file.write(buf) as isize,
+            Err(err) => err.errno(),
+        }
     } else {
         -1
     }
@@ -44,7 +51,12 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     if let Some(file) = &inner.fd_table[fd] {
         let file = file.clone();
         drop(inner);
-        file.read(UserBuf::new(translated_byte_buffer(token, buf, len))) as isize
+        // it may sleep, don't keep task alive on kernel stack
+        drop(task);
+        match user_buffer(token, buf, len, true) {
+            Ok(buf) => file.read(buf) as isize,
+            Err(err) => err.errno(),
+        }
     } else {
         -1
     }
@@ -53,7 +65,10 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let task = cur_task().unwrap();
     let token = cur_user_token();
-    let path = translated_str(token, path);
+    let path = match strncpy_from_user(token, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return err.errno(),
+    };
     if let Some(inode) = inode::open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = task.inner.borrow_mut();
         let fd = inner.alloc_fd();
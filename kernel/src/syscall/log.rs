@@ -0,0 +1,50 @@
+use crate::console::{set_log_level, set_module_log_level, LogLevel};
+use crate::klog;
+use crate::mm::uaccess::{copy_to_user, strncpy_from_user};
+use crate::task::processor::cur_user_token;
+
+/// # sys_log_level
+/// change kernel log level, for the whole kernel if `module` is null,
+/// otherwise for `module` and its children, like `mm::memory_set`
+///
+/// level: 0 off, 1 error, 2 warn, 3 info, 4 debug, 5 trace
+pub fn sys_log_level(level: usize, module: *const u8) -> isize {
+    let level = match LogLevel::from_usize(level) {
+        Some(level) => level,
+        None => return -1,
+    };
+
+    if module.is_null() {
+        set_log_level(level);
+    } else {
+        // module path is short, a long one can't match anything
+        let module = match strncpy_from_user(cur_user_token(), module, 256) {
+            Ok(module) => module,
+            Err(err) => return err.errno(),
+        };
+        set_module_log_level(module.as_str(), level);
+    }
+    0
+}
+
+/// # sys_syslog
+/// copy records in kernel log buffer to `buf`, only the latest records are
+/// copied if `buf` is not large enough
+///
+/// @return the len copied, `EFAULT` if `buf` can't hold it
+pub fn sys_syslog(buf: *mut u8, len: usize) -> isize {
+    let mut contents = klog::read_all();
+    if contents.len() > len {
+        let mut start = contents.len() - len;
+        // don't begin with half a record
+        while start < contents.len() && contents[start - 1] != b'\n' {
+            start += 1;
+        }
+        contents.drain(..start);
+    }
+
+    match copy_to_user(cur_user_token(), buf, &contents) {
+        Ok(()) => contents.len() as isize,
+        Err(err) => err.errno(),
+    }
+}
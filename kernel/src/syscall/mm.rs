@@ -0,0 +1,193 @@
+use super::errno::*;
+use crate::config::PAGE_SIZE;
+use crate::mm::{
+    address::{VirtAddr, VirtPageNum},
+    memory_set::{FileMapping, MapPermission},
+    shm, MmResult,
+};
+use crate::task::processor::cur_task;
+use alloc::sync::Arc;
+
+/// map `segment` into current process, @return its address
+fn attach(segment: MmResult<Arc<shm::ShmSegment>>) -> isize {
+    let segment = match segment {
+        Ok(segment) => segment,
+        Err(err) => return err.errno(),
+    };
+    let task = cur_task().unwrap();
+    let mut inner = task.borrow_mut();
+    match inner.memory_set.attach_shared(segment) {
+        Ok(va) => va.0 as isize,
+        Err(err) => err.errno(),
+    }
+}
+
+/// # sys_shm_create
+/// create a shared memory segment of at least `size` bytes for `key` and
+/// attach it, @return address of the segment
+pub fn sys_shm_create(key: usize, size: usize) -> isize {
+    attach(shm::create(
+        key,
+        size / PAGE_SIZE + (size % PAGE_SIZE != 0) as usize,
+    ))
+}
+
+/// # sys_shm_attach
+/// attach segment of `key` created by another process, @return its address
+pub fn sys_shm_attach(key: usize) -> isize {
+    attach(shm::get(key))
+}
+
+/// # sys_shm_detach
+/// detach segment attached at `addr`, it's freed after the last detach
+pub fn sys_shm_detach(addr: usize) -> isize {
+    let task = cur_task().unwrap();
+    let mut inner = task.borrow_mut();
+    match inner.memory_set.detach_shared(VirtAddr::from(addr)) {
+        Ok(()) => 0,
+        Err(err) => err.errno(),
+    }
+}
+
+/// page protection of `sys_mprotect` and `sys_mmap`, same as Linux
+const PROT_READ: usize = 1 << 0;
+const PROT_WRITE: usize = 1 << 1;
+const PROT_EXEC: usize = 1 << 2;
+
+/// flags of `sys_mmap`, same as Linux
+const MAP_SHARED: usize = 1 << 0;
+const MAP_PRIVATE: usize = 1 << 1;
+
+/// Writable pages are readable too, since riscv doesn't allow write-only
+/// pages, and `PROT_NONE` pages fault on any access
+fn prot_to_perm(prot: usize) -> Option<MapPermission> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return None;
+    }
+    let mut perm = MapPermission::empty();
+    if prot & (PROT_READ | PROT_WRITE) != 0 {
+        perm |= MapPermission::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        perm |= MapPermission::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        perm |= MapPermission::X;
+    }
+    Some(perm)
+}
+
+/// pages of `[addr, addr + len)`, `addr` must be page aligned
+fn user_range(addr: usize, len: usize) -> Result<(VirtPageNum, VirtPageNum), isize> {
+    let start = VirtAddr::from(addr);
+    if !start.aligned() {
+        return Err(-EINVAL);
+    }
+    match addr.checked_add(len) {
+        Some(end) => Ok((start.floor(), VirtAddr::from(end).ceil())),
+        None => Err(-ENOMEM),
+    }
+}
+
+/// # sys_mprotect
+/// change permission of pages in `[addr, addr + len)`
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let (start, end) = match user_range(addr, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+    let perm = match prot_to_perm(prot) {
+        Some(perm) => perm,
+        None => return -EINVAL,
+    };
+
+    let task = cur_task().unwrap();
+    let mut inner = task.borrow_mut();
+    match inner.memory_set.mprotect(start, end, perm) {
+        Ok(()) => 0,
+        Err(err) => err.errno(),
+    }
+}
+
+/// # sys_mmap
+/// map `len` bytes of file `fd` from `offset`, @return address of mapping.
+/// `addr` is only a hint and ignored, pages are read from file on first access.
+/// Either `MAP_SHARED` or `MAP_PRIVATE` must be given in `flags`
+pub fn sys_mmap(
+    _addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    let perm = match prot_to_perm(prot) {
+        Some(perm) => perm,
+        None => return -EINVAL,
+    };
+    let shared = match flags {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return -EINVAL,
+    };
+    if len == 0 || offset % PAGE_SIZE != 0 {
+        return -EINVAL;
+    }
+
+    let task = cur_task().unwrap();
+    let mut inner = task.borrow_mut();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -EBADF,
+    };
+    let inode = match file.inode() {
+        Some(inode) => inode,
+        None => return -ENODEV,
+    };
+    if !file.readable() || (shared && perm.contains(MapPermission::W) && !file.writeable()) {
+        return -EACCES;
+    }
+
+    let pages = len / PAGE_SIZE + (len % PAGE_SIZE != 0) as usize;
+    let mapping = FileMapping {
+        inode,
+        shared,
+        writable: file.writeable(),
+    };
+    match inner
+        .memory_set
+        .mmap_file(pages, mapping, offset / PAGE_SIZE, perm)
+    {
+        Ok(va) => va.0 as isize,
+        Err(err) => err.errno(),
+    }
+}
+
+/// # sys_munmap
+/// unmap file mappings in `[addr, addr + len)`, shared mappings are synced first
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    let (start, end) = match user_range(addr, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+    let task = cur_task().unwrap();
+    let mut inner = task.borrow_mut();
+    match inner.memory_set.munmap(start, end) {
+        Ok(()) => 0,
+        Err(err) => err.errno(),
+    }
+}
+
+/// # sys_msync
+/// write dirty pages of shared file mappings in `[addr, addr + len)` to file.
+/// Writes stay in page cache until msync, munmap or exit, and msync always
+/// writes them synchronously, so `flags` makes no difference
+pub fn sys_msync(addr: usize, len: usize, _flags: usize) -> isize {
+    let (start, end) = match user_range(addr, len) {
+        Ok(range) => range,
+        Err(errno) => return errno,
+    };
+    let task = cur_task().unwrap();
+    task.borrow_mut().memory_set.msync(start, end);
+    0
+}
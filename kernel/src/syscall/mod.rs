@@ -1,5 +1,10 @@
+mod errno;
 pub mod fs;
+mod log;
+mod mm;
 mod proc;
+mod profile;
+mod trace;
 
 /// syscall number
 const SYSCALL_SHUTDOWN: usize = 48;
@@ -8,32 +13,79 @@ const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_SYSLOG: usize = 116;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_FORK: usize = 220;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_SPAWN: usize = 400;
+const SYSCALL_LOG_LEVEL: usize = 401;
+const SYSCALL_TRACE: usize = 402;
+const SYSCALL_PROFILE_START: usize = 403;
+const SYSCALL_PROFILE_STOP: usize = 404;
+const SYSCALL_PROFILE_READ: usize = 405;
+const SYSCALL_KSYM: usize = 406;
+const SYSCALL_SHM_CREATE: usize = 407;
+const SYSCALL_SHM_ATTACH: usize = 408;
+const SYSCALL_SHM_DETACH: usize = 409;
 
 use fs::*;
+use log::*;
+use mm::*;
 pub use proc::*;
+use profile::*;
+use trace::*;
 
 /// general syscall implementation
-pub fn syscall(id: usize, args: [usize; 3]) -> isize {
-    match id {
+pub fn syscall(id: usize, args: [usize; 6]) -> isize {
+    let call = if is_traced() {
+        let call = format_call(id, args);
+        if id == SYSCALL_EXIT || id == SYSCALL_SHUTDOWN {
+            trace_syscall(&call, None);
+        }
+        Some(call)
+    } else {
+        None
+    };
+
+    let ret = match id {
         SYSCALL_SHUTDOWN => sys_shutdown(),
         SYSCALL_OPEN => sys_open(args[0] as *const u8, args[1] as u32),
         SYSCALL_CLOSE => sys_close(args[0]),
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_SYSLOG => sys_syslog(args[0] as *mut u8, args[1]),
         SYSCALL_YIELD => sys_yield(),
         SYSCALL_TIME => sys_time(),
         SYSCALL_FORK => sys_fork(),
         SYSCALL_EXEC => sys_exec(args[0] as *const u8),
-        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MSYNC => sys_msync(args[0], args[1], args[2]),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32, args[2] as *mut _),
         SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_LOG_LEVEL => sys_log_level(args[0], args[1] as *const u8),
+        SYSCALL_TRACE => sys_trace(args[0]),
+        SYSCALL_PROFILE_START => sys_profile_start(args[0] as isize),
+        SYSCALL_PROFILE_STOP => sys_profile_stop(args[0] as isize, args[1]),
+        SYSCALL_PROFILE_READ => sys_profile_read(args[0] as isize, args[1] as *mut _, args[2]),
+        SYSCALL_KSYM => sys_ksym(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_SHM_CREATE => sys_shm_create(args[0], args[1]),
+        SYSCALL_SHM_ATTACH => sys_shm_attach(args[0]),
+        SYSCALL_SHM_DETACH => sys_shm_detach(args[0]),
         _ => panic!("Unsupported syscall id:{}", id),
+    };
+
+    if let Some(call) = call {
+        trace_syscall(&call, Some(ret));
     }
+    ret
 }
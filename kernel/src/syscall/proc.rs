@@ -1,14 +1,14 @@
 use alloc::format;
-use alloc::sync::Arc;
 
 use crate::console::{println_with_color, YELLOW};
 use crate::fs::inode::{open_file, OpenFlags};
-use crate::mm::page_table;
-use crate::mm::page_table::translated_str;
+use crate::mm::uaccess::{put_user, strncpy_from_user};
 use crate::sbi::shutdown;
+use crate::syscall::fs::PATH_MAX;
 use crate::task::exit_cur_and_run_next;
 use crate::task::processor::{cur_task, cur_user_token};
-use crate::task::{self, processor, scheduler, task::ProcessControlBlock};
+use crate::task::task::{ProcessControlBlock, Rusage, Zombie};
+use crate::task::{self, processor, scheduler};
 use crate::timer::time_ms;
 
 pub fn sys_shutdown() -> ! {
@@ -21,7 +21,7 @@ pub fn sys_exit(exit_code: i32) -> ! {
 }
 
 pub fn sys_yield() -> isize {
-    task::suspend_cur_and_run_next();
+    task::yield_cur_and_run_next();
     0
 }
 
@@ -31,7 +31,10 @@ pub fn sys_time() -> isize {
 
 pub fn sys_fork() -> isize {
     let cur_task = processor::cur_task().unwrap();
-    let child = ProcessControlBlock::fork(&cur_task);
+    let child = match ProcessControlBlock::fork(&cur_task) {
+        Ok(child) => child,
+        Err(err) => return err.errno(),
+    };
     let pid = child.pid.0;
 
     // set child return code = 0
@@ -42,29 +45,38 @@ pub fn sys_fork() -> isize {
     pid as isize
 }
 
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// # sys_waitpid
+/// reap a zombie child, `pid` -1 means any child. `rusage_ptr` can be null
+///
+/// @return pid of the child, -1 if no such child, -2 if it hasn't exited,
+/// `EFAULT` if results can't be written, then the child is not reaped
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, rusage_ptr: *mut Rusage) -> isize {
     let task = cur_task().unwrap();
 
-    let mut inner = task.borrow_mut();
-    let child = inner
-        .children
-        .iter()
-        .enumerate()
-        .find(|&(_, p)| pid == -1 || p.getpid() == pid as usize);
-    if let Some((idx, p)) = child {
-        if p.borrow_mut().is_zombie() {
-            let del = inner.children.remove(idx);
-            assert_eq!(Arc::strong_count(&del), 1);
-            let del_pid = del.getpid();
-            let exit_code = del.borrow_mut().exit_code;
-            *page_table::translated_refmut(inner.user_token(), exit_code_ptr) = exit_code;
-            del_pid as isize
-        } else {
-            -2
+    let inner = task.borrow_mut();
+    let is_child = |child: usize| pid == -1 || child == pid as usize;
+    let idx = match inner.zombies.iter().position(|z| is_child(z.pid.0)) {
+        Some(idx) => idx,
+        None if inner.children.iter().any(|p| is_child(p.getpid())) => return -2,
+        None => return -1,
+    };
+    let Zombie {
+        exit_code, rusage, ..
+    } = inner.zombies[idx];
+    let token = inner.user_token();
+    // user pages may have to be mapped by copy, which borrows the task
+    drop(inner);
+    if let Err(err) = put_user(token, exit_code_ptr, exit_code) {
+        return err.errno();
+    }
+    if !rusage_ptr.is_null() {
+        if let Err(err) = put_user(token, rusage_ptr, rusage) {
+            return err.errno();
         }
-    } else {
-        -1
     }
+    // pid is free to reuse once zombie is dropped
+    let zombie = task.borrow_mut().zombies.remove(idx);
+    zombie.pid.0 as isize
 }
 
 pub fn sys_getpid() -> isize {
@@ -79,14 +91,19 @@ pub fn sys_spawn(path: *const u8) -> isize {
 
 pub fn sys_exec(path: *const u8) -> isize {
     let token = cur_user_token();
-    let path = translated_str(token, path);
+    let path = match strncpy_from_user(token, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return err.errno(),
+    };
     // read elf file as read only becase we don't want
     // our executable file get modify
     if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
         let all_data = app_inode.read_all();
         let task = cur_task().unwrap();
-        task.exec(all_data.as_slice());
-        0
+        match task.exec(all_data.as_slice()) {
+            Ok(()) => 0,
+            Err(err) => err.errno(),
+        }
     } else {
         -1
     }
@@ -0,0 +1,78 @@
+use crate::ksym;
+use crate::mm::uaccess::copy_to_user;
+use crate::profile::{self, Sample};
+use crate::task::processor::{cur_task, cur_user_token};
+use core::mem::size_of;
+
+/// pid -1 means current task
+fn profile_pid(pid: isize) -> usize {
+    if pid == -1 {
+        cur_task().unwrap().getpid()
+    } else {
+        pid as usize
+    }
+}
+
+/// # sys_profile_start
+/// sample pc of task `pid` on every timer interrupt
+pub fn sys_profile_start(pid: isize) -> isize {
+    if profile::start(profile_pid(pid)) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// # sys_profile_stop
+/// stop sampling task `pid`, and release its samples if `release` != 0
+pub fn sys_profile_stop(pid: isize, release: usize) -> isize {
+    if profile::stop(profile_pid(pid), release != 0) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// # sys_profile_read
+/// copy at most `len` samples of task `pid` to `buf`, most frequent first
+///
+/// @return number of samples copied
+pub fn sys_profile_read(pid: isize, buf: *mut Sample, len: usize) -> isize {
+    let pid = profile_pid(pid);
+    let (samples, dropped) = match profile::read(pid) {
+        Some(res) => res,
+        None => return -1,
+    };
+    if dropped != 0 {
+        warn!("[profile] {} samples of pid {} dropped", dropped, pid);
+    }
+
+    let samples = &samples[..samples.len().min(len)];
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            samples.as_ptr() as *const u8,
+            samples.len() * size_of::<Sample>(),
+        )
+    };
+    match copy_to_user(cur_user_token(), buf as *mut u8, bytes) {
+        Ok(()) => samples.len() as isize,
+        Err(err) => err.errno(),
+    }
+}
+
+/// # sys_ksym
+/// copy name of kernel symbol containing `addr` to `buf`, truncated to `len`
+///
+/// @return length copied, -1 if no symbol found
+pub fn sys_ksym(addr: usize, buf: *mut u8, len: usize) -> isize {
+    match ksym::lookup(addr) {
+        Some((name, _)) => {
+            let name = &name.as_bytes()[..name.len().min(len)];
+            match copy_to_user(cur_user_token(), buf, name) {
+                Ok(()) => name.len() as isize,
+                Err(err) => err.errno(),
+            }
+        }
+        None => -1,
+    }
+}
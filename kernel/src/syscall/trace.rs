@@ -0,0 +1,119 @@
+//! # Syscall tracing
+//!
+//! When `trace` of a process is set, every syscall it makes is logged as
+//! `[pid] name(args) = ret`, strings are decoded from user space.
+//! `trace` is inherited on fork and kept across exec, see `strace`.
+
+use super::fs::PATH_MAX;
+use super::*;
+use crate::console::log;
+use crate::mm::uaccess::strncpy_from_user;
+use crate::task::processor::{cur_task, cur_user_token};
+use alloc::format;
+use alloc::string::String;
+
+/// # sys_trace
+/// turn syscall tracing of current process on or off
+pub fn sys_trace(enable: usize) -> isize {
+    cur_task().unwrap().borrow_mut().trace = enable != 0;
+    0
+}
+
+pub fn is_traced() -> bool {
+    cur_task().map_or(false, |task| task.borrow_mut().trace)
+}
+
+fn syscall_name(id: usize) -> &'static str {
+    match id {
+        SYSCALL_SHUTDOWN => "shutdown",
+        SYSCALL_OPEN => "open",
+        SYSCALL_CLOSE => "close",
+        SYSCALL_READ => "read",
+        SYSCALL_WRITE => "write",
+        SYSCALL_EXIT => "exit",
+        SYSCALL_SYSLOG => "syslog",
+        SYSCALL_YIELD => "yield",
+        SYSCALL_TIME => "time",
+        SYSCALL_GETPID => "getpid",
+        SYSCALL_FORK => "fork",
+        SYSCALL_EXEC => "exec",
+        SYSCALL_MUNMAP => "munmap",
+        SYSCALL_MMAP => "mmap",
+        SYSCALL_MSYNC => "msync",
+        SYSCALL_MPROTECT => "mprotect",
+        SYSCALL_WAITPID => "waitpid",
+        SYSCALL_SPAWN => "spawn",
+        SYSCALL_LOG_LEVEL => "log_level",
+        SYSCALL_TRACE => "trace",
+        SYSCALL_PROFILE_START => "profile_start",
+        SYSCALL_PROFILE_STOP => "profile_stop",
+        SYSCALL_PROFILE_READ => "profile_read",
+        SYSCALL_KSYM => "ksym",
+        SYSCALL_SHM_CREATE => "shm_create",
+        SYSCALL_SHM_ATTACH => "shm_attach",
+        SYSCALL_SHM_DETACH => "shm_detach",
+        _ => "unknown",
+    }
+}
+
+fn user_str(ptr: usize) -> String {
+    if ptr == 0 {
+        String::from("NULL")
+    } else {
+        match strncpy_from_user(cur_user_token(), ptr as *const u8, PATH_MAX) {
+            Ok(s) => format!("{:?}", s),
+            Err(err) => format!("{:#x} ({:?})", ptr, err),
+        }
+    }
+}
+
+/// decode arguments before syscall, user memory may be gone after it, e.g. `exec`
+pub fn format_call(id: usize, args: [usize; 6]) -> String {
+    let args = match id {
+        SYSCALL_SHUTDOWN | SYSCALL_YIELD | SYSCALL_TIME | SYSCALL_GETPID | SYSCALL_FORK => {
+            String::new()
+        }
+        SYSCALL_OPEN => format!("{}, {:#x}", user_str(args[0]), args[1]),
+        SYSCALL_CLOSE | SYSCALL_TRACE => format!("{}", args[0]),
+        SYSCALL_MPROTECT | SYSCALL_MSYNC => {
+            format!("{:#x}, {}, {:#x}", args[0], args[1], args[2])
+        }
+        SYSCALL_MUNMAP => format!("{:#x}, {}", args[0], args[1]),
+        SYSCALL_MMAP => format!(
+            "{:#x}, {}, {:#x}, {:#x}, {}, {:#x}",
+            args[0], args[1], args[2], args[3], args[4] as isize, args[5]
+        ),
+        SYSCALL_SHM_CREATE => format!("{:#x}, {}", args[0], args[1]),
+        SYSCALL_SHM_ATTACH | SYSCALL_SHM_DETACH => format!("{:#x}", args[0]),
+        SYSCALL_READ | SYSCALL_WRITE => format!("{}, {:#x}, {}", args[0], args[1], args[2]),
+        SYSCALL_EXIT => format!("{}", args[0] as i32),
+        SYSCALL_SYSLOG => format!("{:#x}, {}", args[0], args[1]),
+        SYSCALL_EXEC | SYSCALL_SPAWN => user_str(args[0]),
+        SYSCALL_WAITPID => format!("{}, {:#x}, {:#x}", args[0] as isize, args[1], args[2]),
+        SYSCALL_LOG_LEVEL => format!("{}, {}", args[0], user_str(args[1])),
+        SYSCALL_PROFILE_START => format!("{}", args[0] as isize),
+        SYSCALL_PROFILE_STOP => format!("{}, {}", args[0] as isize, args[1]),
+        SYSCALL_PROFILE_READ => format!("{}, {:#x}, {}", args[0] as isize, args[1], args[2]),
+        _ => format!("{:#x}, {:#x}, {:#x}", args[0], args[1], args[2]),
+    };
+    format!("{}({})", syscall_name(id), args)
+}
+
+/// `ret` is `None` if syscall doesn't return, e.g. `exit`
+pub fn trace_syscall(call: &str, ret: Option<isize>) {
+    let pid = cur_task().unwrap().getpid();
+    match ret {
+        Some(ret) => log(
+            "STRACE",
+            "\x1b[34;1m",
+            "\x1b[34m",
+            format_args!("[{}] {} = {}", pid, call, ret),
+        ),
+        None => log(
+            "STRACE",
+            "\x1b[34;1m",
+            "\x1b[34m",
+            format_args!("[{}] {} = ?", pid, call),
+        ),
+    }
+}
@@ -0,0 +1,180 @@
+//! # Core dump
+//!
+//! Write an ELF core file of a crashed process to easy-fs as `core.<pid>`,
+//! which can be loaded by gdb together with the program:
+//! - `PT_NOTE`: `NT_PRSTATUS` with registers in `TrapContext`
+//! - `PT_LOAD`: one for every `MapArea` that user can access
+
+use super::task::ProcessControlBlock;
+use crate::config::PAGE_SIZE;
+use crate::fs::inode::{open_file, OpenFlags};
+use crate::mm::memory_set::MapPermission;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const SIGILL: i32 = 4;
+pub const SIGSEGV: i32 = 11;
+
+const ET_CORE: u16 = 4;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// size of `struct elf_prstatus` of riscv64 linux
+const PRSTATUS_SIZE: usize = 376;
+/// note header + "CORE\0" padded to 8 bytes + prstatus
+const NOTE_SIZE: usize = 12 + 8 + PRSTATUS_SIZE;
+/// content of pages not mapped yet
+static ZERO_PAGE: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn elf_header(buf: &mut Vec<u8>, phnum: usize) {
+    // magic, 64-bit, little endian, version 1, System V ABI
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    buf.extend_from_slice(&[0; 8]);
+    put_u16(buf, ET_CORE);
+    put_u16(buf, EM_RISCV);
+    put_u32(buf, 1); // e_version
+    put_u64(buf, 0); // e_entry
+    put_u64(buf, ELF_HEADER_SIZE as u64); // e_phoff
+    put_u64(buf, 0); // e_shoff
+    put_u32(buf, 0); // e_flags
+    put_u16(buf, ELF_HEADER_SIZE as u16);
+    put_u16(buf, PROGRAM_HEADER_SIZE as u16);
+    put_u16(buf, phnum as u16);
+    put_u16(buf, 0); // e_shentsize
+    put_u16(buf, 0); // e_shnum
+    put_u16(buf, 0); // e_shstrndx
+}
+
+fn program_header(
+    buf: &mut Vec<u8>,
+    p_type: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    size: usize,
+    align: usize,
+) {
+    put_u32(buf, p_type);
+    put_u32(buf, flags);
+    put_u64(buf, offset as u64);
+    put_u64(buf, vaddr as u64);
+    put_u64(buf, 0); // p_paddr
+    put_u64(buf, size as u64); // p_filesz
+    put_u64(buf, size as u64); // p_memsz
+    put_u64(buf, align as u64);
+}
+
+fn prstatus_note(buf: &mut Vec<u8>, signal: i32, pid: usize, ppid: usize, regs: &[usize; 32]) {
+    put_u32(buf, 5); // namesz
+    put_u32(buf, PRSTATUS_SIZE as u32); // descsz
+    put_u32(buf, NT_PRSTATUS);
+    buf.extend_from_slice(b"CORE\0\0\0\0");
+
+    // pr_info: si_signo, si_code, si_errno
+    put_u32(buf, signal as u32);
+    put_u32(buf, 0);
+    put_u32(buf, 0);
+    // pr_cursig and padding
+    put_u16(buf, signal as u16);
+    put_u16(buf, 0);
+    // pr_sigpend, pr_sighold
+    put_u64(buf, 0);
+    put_u64(buf, 0);
+    // pr_pid, pr_ppid, pr_pgrp, pr_sid
+    put_u32(buf, pid as u32);
+    put_u32(buf, ppid as u32);
+    put_u32(buf, pid as u32);
+    put_u32(buf, pid as u32);
+    // pr_utime, pr_stime, pr_cutime, pr_cstime
+    buf.extend_from_slice(&[0; 64]);
+    // pr_reg: pc, x1-x31
+    for reg in regs.iter() {
+        put_u64(buf, *reg as u64);
+    }
+    // pr_fpvalid and padding
+    put_u32(buf, 0);
+    put_u32(buf, 0);
+}
+
+/// # write_core
+/// dump registers and user memory of `task` to `core.<pid>`
+pub fn write_core(task: &Arc<ProcessControlBlock>, signal: i32) {
+    let pid = task.getpid();
+    let inner = task.borrow_mut();
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(|p| p.upgrade())
+        .map_or(0, |p| p.getpid());
+    let areas = inner.memory_set.user_areas();
+
+    let mut regs = inner.trap_cxt().x;
+    regs[0] = inner.trap_cxt().sepc;
+
+    // headers and note, memory of areas starts at next page
+    let phnum = areas.len() + 1;
+    let note_offset = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * phnum;
+    let mut offset = (note_offset + NOTE_SIZE + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+    let mut buf = Vec::new();
+    elf_header(&mut buf, phnum);
+    program_header(&mut buf, PT_NOTE, 0, note_offset, 0, NOTE_SIZE, 4);
+    for (range, perm) in areas.iter() {
+        let mut flags = 0;
+        if perm.contains(MapPermission::R) {
+            flags |= PF_R;
+        }
+        if perm.contains(MapPermission::W) {
+            flags |= PF_W;
+        }
+        if perm.contains(MapPermission::X) {
+            flags |= PF_X;
+        }
+        let size = (range.end().0 - range.start().0) * PAGE_SIZE;
+        let vaddr = range.start().0 * PAGE_SIZE;
+        program_header(&mut buf, PT_LOAD, flags, offset, vaddr, size, PAGE_SIZE);
+        offset += size;
+    }
+    prstatus_note(&mut buf, signal, pid, ppid, &regs);
+    buf.resize((buf.len() + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE, 0);
+
+    let name = format!("core.{}", pid);
+    let file = match open_file(name.as_str(), OpenFlags::CREATE | OpenFlags::WRONLY) {
+        Some(file) => file,
+        None => {
+            error!("[kernel] can't create {}", name);
+            return;
+        }
+    };
+    file.write_all(buf.as_slice());
+    for (range, _) in areas {
+        for vpn in range {
+            // pages of file mapping may not be read yet
+            match inner.memory_set.translate(vpn).filter(|pte| pte.is_valid()) {
+                Some(pte) => file.write_all(pte.ppn().bytes_array()),
+                None => file.write_all(&ZERO_PAGE),
+            };
+        }
+    }
+    kernel!("core dumped to {}", name);
+}
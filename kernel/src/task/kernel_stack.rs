@@ -1,8 +1,9 @@
 use crate::{
-    config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE},
+    config::{KERNEL_STACK_SIZE, MEMORY_END, PAGE_SIZE, TRAMPOLINE},
     mm::{
         address::VirtAddr,
         memory_set::{MapPermission, KERNEL_SPACE},
+        MmResult,
     },
 };
 pub struct KernelStack {
@@ -11,20 +12,24 @@ pub struct KernelStack {
 }
 
 impl KernelStack {
-    pub fn new(pid: usize) -> Self {
+    pub fn new(pid: usize) -> MmResult<Self> {
         let (bottom, top) = kernel_stack_position(pid);
         KERNEL_SPACE.borrow_mut().insert_framed_area(
             bottom.into(),
             top.into(),
             MapPermission::R | MapPermission::W,
-        );
-        Self { pid, top }
+        )?;
+        Ok(Self { pid, top })
     }
 
     pub fn top(&self) -> usize {
         self.top
     }
 
+    pub fn bottom(&self) -> usize {
+        kernel_stack_position(self.pid).0
+    }
+
     pub fn push<T>(&mut self, v: T) -> *mut T
     where
         T: Sized,
@@ -46,8 +51,32 @@ impl Drop for KernelStack {
     }
 }
 
+/// # kernel stack layout
+/// every process owns a slot of `KERNEL_STACK_SIZE + PAGE_SIZE` below `TRAMPOLINE`,
+/// stack lies at the top of slot and the lowest page of slot is never mapped,
+/// which works as a guard page
 pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
     let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
+
+/// find the pid whose kernel stack slot(stack and guard page) contains `addr`
+pub fn kernel_stack_owner(addr: usize) -> Option<usize> {
+    // kernel stacks only live above physical memory mapping
+    if addr < MEMORY_END || addr >= TRAMPOLINE {
+        return None;
+    }
+    Some((TRAMPOLINE - 1 - addr) / (KERNEL_STACK_SIZE + PAGE_SIZE))
+}
+
+/// find the pid whose kernel stack guard page contains `addr`
+pub fn kernel_stack_guard_owner(addr: usize) -> Option<usize> {
+    let pid = kernel_stack_owner(addr)?;
+    let (bottom, _) = kernel_stack_position(pid);
+    if addr < bottom && addr >= bottom - PAGE_SIZE {
+        Some(pid)
+    } else {
+        None
+    }
+}
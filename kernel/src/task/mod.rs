@@ -1,5 +1,7 @@
 mod context;
+pub mod coredump;
 pub mod kernel_stack;
+pub mod oom;
 pub mod pid;
 pub mod processor;
 pub mod scheduler;
@@ -9,18 +11,22 @@ pub mod task;
 
 use crate::fs::inode::open_file;
 use crate::fs::inode::OpenFlags;
+use crate::profile;
+use crate::timer;
 use alloc::sync::Arc;
 
 pub use context::TaskContext;
 use lazy_static::*;
 pub use switch::__switch;
-use task::{ProcessControlBlock, TaskStatus};
+use task::{ProcessControlBlock, TaskStatus, Zombie};
 
 use self::processor::cur_task;
 pub use self::processor::run;
 use self::processor::schedule;
+use self::processor::set_exited_task;
 use self::processor::take_cur_task;
-use self::scheduler::add_task;
+use self::scheduler::{add_task, has_ready_task, sleep_task};
+use crate::timer::{deadline_after_ms, SLICE_MS};
 
 lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = Arc::new({
@@ -34,37 +40,122 @@ pub fn add_initproc() {
     add_task(INITPROC.clone());
 }
 
-pub fn suspend_cur_and_run_next() {
+/// mark current task ready, `in_kernel` tells if it's switched out in the
+/// middle of kernel work
+fn ready_cur(in_kernel: bool) -> (Arc<ProcessControlBlock>, *mut TaskContext) {
     let task = cur_task().unwrap();
 
     let mut task_inner = task.borrow_mut();
     let task_ptr = &mut task_inner.cxt as *mut TaskContext;
 
     task_inner.status = TaskStatus::Ready;
+    task_inner.in_kernel = in_kernel;
+    task_inner.charge_time(false);
     drop(task_inner);
 
+    (task, task_ptr)
+}
+
+pub fn suspend_cur_and_run_next() {
+    let (task, task_ptr) = ready_cur(true);
     add_task(task);
     schedule(task_ptr);
 }
 
-pub fn exit_cur_and_run_next(exit_code: i32) {
-    let task = take_cur_task().unwrap();
+/// time slice runs out when current task is about to return to user, its
+/// kernel stack owns nothing, so OOM killer may free its memory at once
+pub fn preempt_cur_and_run_next() {
+    let (task, task_ptr) = ready_cur(false);
+    add_task(task);
+    schedule(task_ptr);
+}
+
+/// for tasks polling something, e.g. stdin: sleep for `ms` instead of
+/// spinning, so that processor can idle when nothing else runs
+pub fn suspend_cur_and_sleep(ms: usize) {
+    let (task, task_ptr) = ready_cur(true);
+    sleep_task(deadline_after_ms(ms), task);
+    schedule(task_ptr);
+}
+
+/// if current task is killed, it should give up waiting and go back to user
+pub fn cur_killed() -> bool {
+    cur_task().map_or(false, |task| task.borrow_mut().killed)
+}
+
+/// give processor to other ready task, or sleep for a time slice if there's none
+pub fn yield_cur_and_run_next() {
+    if has_ready_task() {
+        suspend_cur_and_run_next();
+    } else {
+        suspend_cur_and_sleep(SLICE_MS);
+    }
+}
+
+/// # make_zombie
+/// Release resources of `task` except its PCB and kernel stack, which are
+/// in use until we switch away from it, see `bury`. `task` must not run again.
+pub fn make_zombie(task: &Arc<ProcessControlBlock>, exit_code: i32) {
     let mut inner = task.borrow_mut();
 
     inner.status = TaskStatus::Zombie;
     inner.exit_code = exit_code;
 
+    // free memory first, we may be here because memory runs out
+    inner.fd_table.clear();
+    // kernel runs in kernel space, it's safe to free page table of user
+    inner.memory_set.recycle_pages();
+
     let mut initproc = INITPROC.borrow_mut();
     for child in inner.children.iter() {
         child.borrow_mut().parent = Some(Arc::downgrade(&INITPROC));
         initproc.children.push(child.clone());
     }
+    initproc.zombies.append(&mut inner.zombies);
     drop(initproc);
-
     inner.children.clear();
-    inner.memory_set.recycle_pages();
     drop(inner);
-    drop(task);
+
+    timer::cancel_deadlines(task.getpid());
+    // samples are kept for reading, but no more sampling
+    profile::stop(task.getpid(), false);
+}
+
+/// # bury
+/// Free PCB, kernel stack and page table of `task` exited by
+/// `exit_cur_and_run_next`, and leave a zombie record of pid, exit code
+/// and rusage to its parent. Called on boot stack after switching away.
+pub fn bury(task: Arc<ProcessControlBlock>) {
+    let mut inner = task.borrow_mut();
+    let parent = match inner.parent.take().and_then(|parent| parent.upgrade()) {
+        Some(parent) => parent,
+        // initproc is kept by `INITPROC`
+        None => return,
+    };
+    let (exit_code, rusage) = (inner.exit_code, inner.rusage);
+    drop(inner);
+
+    let mut parent_inner = parent.borrow_mut();
+    parent_inner
+        .children
+        .retain(|child| !Arc::ptr_eq(child, &task));
+    let ProcessControlBlock { pid, .. } = Arc::try_unwrap(task)
+        .ok()
+        .expect("exited task is still in use");
+    parent_inner.zombies.push(Zombie {
+        pid,
+        exit_code,
+        rusage,
+    });
+}
+
+pub fn exit_cur_and_run_next(exit_code: i32) {
+    let task = take_cur_task().unwrap();
+    task.borrow_mut().charge_time(false);
+    // victim of OOM killer can't do it, its writes to shared mapping are lost
+    task.borrow_mut().memory_set.sync_files();
+    make_zombie(&task, exit_code);
+    set_exited_task(task);
 
     let mut _unused = TaskContext::from_zero();
     schedule(&mut _unused as *mut TaskContext);
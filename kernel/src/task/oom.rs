@@ -0,0 +1,79 @@
+//! # OOM killer
+//!
+//! When kernel itself runs out of memory, e.g. kernel heap or virtio dma,
+//! we kill the process using most frames to free its memory at once.
+//! Memory requests from user just fail with ENOMEM instead.
+//!
+//! OOM killer runs inside allocators, where the failing allocation may hold
+//! any lock, so it only frees user memory of the victim and marks it killed.
+//! The victim releases the rest itself by exiting when it's about to return
+//! to user. A victim switched out in the middle of kernel work may be using
+//! its memory, e.g. buffer of a sleeping `read`, so it's only marked, and
+//! nothing is freed until it exits.
+
+use super::scheduler::PROC_SCHEDULER;
+use super::{task::ProcessControlBlock, INITPROC};
+use crate::mm::frame_allocator::FRAME_ALLOCATOR;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// killing a process may allocate memory, don't kill again inside it
+static KILLING: AtomicBool = AtomicBool::new(false);
+
+/// # oom_kill
+/// kill the largest ready or sleeping process except initproc, current task
+/// is never killed since we are running on its kernel stack
+///
+/// @return false if no memory is freed
+pub fn oom_kill() -> bool {
+    if KILLING.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+    let freed = kill_largest();
+    KILLING.store(false, Ordering::Relaxed);
+    freed
+}
+
+fn kill_largest() -> bool {
+    // kernel may be starved while changing them, give up instead of
+    // panicking on `RefCell`
+    let scheduler = match PROC_SCHEDULER.try_borrow_mut() {
+        Some(scheduler) => scheduler,
+        None => return false,
+    };
+    if FRAME_ALLOCATOR.try_borrow_mut().is_none() {
+        return false;
+    }
+    // victim whose memory can be freed now is preferred
+    let frames = |in_kernel: bool| {
+        move |task: &Arc<ProcessControlBlock>| {
+            if Arc::ptr_eq(task, &INITPROC) {
+                return 0;
+            }
+            task.inner.try_borrow_mut().map_or(0, |inner| {
+                if inner.killed || inner.in_kernel != in_kernel {
+                    0
+                } else {
+                    inner.memory_set.frame_count()
+                }
+            })
+        }
+    };
+    let victim = scheduler
+        .max_by_key(frames(false))
+        .or_else(|| scheduler.max_by_key(frames(true)));
+    drop(scheduler);
+
+    let victim = match victim {
+        Some(victim) => victim,
+        None => return false,
+    };
+    let mut inner = victim.borrow_mut();
+    inner.killed = true;
+    if inner.in_kernel {
+        return false;
+    }
+    // kernel runs in kernel space, it's safe to free page table of user
+    inner.memory_set.recycle_pages();
+    true
+}
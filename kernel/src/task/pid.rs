@@ -1,5 +1,6 @@
 use alloc::vec::Vec;
 
+use crate::profile;
 use crate::sync::UniProcSafeCell;
 
 pub struct Pid(pub usize);
@@ -52,5 +53,8 @@ lazy_static! {
 }
 
 pub fn pid_alloc() -> Pid {
-    PID_ALLOCATOR.borrow_mut().alloc()
+    let pid = PID_ALLOCATOR.borrow_mut().alloc();
+    // samples left by last task with this pid don't belong to the new one
+    profile::stop(pid.0, true);
+    pid
 }
@@ -1,10 +1,17 @@
 use super::__switch;
-use super::scheduler::fetch_task;
+use super::bury;
+use super::scheduler::{fetch_task, has_ready_task};
 use super::task::TaskStatus;
 use super::{task::ProcessControlBlock, TaskContext};
 use crate::sync::UniProcSafeCell;
-use crate::trap::TrapContext;
+use crate::timer::{start_slice, stop_slice};
+use crate::trap::{
+    disable_kernel_interrupt, enable_kernel_interrupt, restore_kernel_interrupt,
+    set_kernel_stack_bottom, TrapContext,
+};
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::asm::wfi;
 
 /// # Processor
 /// Processor is the abstraction of one HART(a special concept in RISC-V)
@@ -13,6 +20,8 @@ use alloc::sync::Arc;
 /// We will shift a running process into this strcture and manage it.
 pub struct Processor {
     cur: Option<Arc<ProcessControlBlock>>,
+    /// task exited on this processor, it's buried after switching away from it
+    exited: Option<Arc<ProcessControlBlock>>,
     idle_task_cxt: TaskContext,
 }
 
@@ -20,6 +29,7 @@ impl Processor {
     pub fn new() -> Self {
         Self {
             cur: None,
+            exited: None,
             idle_task_cxt: TaskContext::from_zero(),
         }
     }
@@ -44,10 +54,41 @@ lazy_static! {
     pub static ref PROCESSOR: UniProcSafeCell<Processor> = UniProcSafeCell::new(Processor::new());
 }
 
+/// set by timer interrupt taken in kernel, current task should yield
+/// before it goes back to user space.
+///
+/// It can't live in `Processor`, because interrupted code may hold `PROCESSOR`
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_need_resched() {
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+}
+
+pub fn take_need_resched() -> bool {
+    NEED_RESCHED.swap(false, Ordering::Relaxed)
+}
+
+/// pid of task running on this processor, `NO_PID` if none. It is for
+/// interrupt handlers, which can't borrow `PROCESSOR`
+static RUNNING_PID: AtomicUsize = AtomicUsize::new(NO_PID);
+const NO_PID: usize = usize::MAX;
+
+pub fn running_pid() -> Option<usize> {
+    match RUNNING_PID.load(Ordering::Relaxed) {
+        NO_PID => None,
+        pid => Some(pid),
+    }
+}
+
 pub fn take_cur_task() -> Option<Arc<ProcessControlBlock>> {
     PROCESSOR.borrow_mut().take_cur()
 }
 
+/// `task` is running its last code, see `bury`
+pub fn set_exited_task(task: Arc<ProcessControlBlock>) {
+    PROCESSOR.borrow_mut().exited = Some(task);
+}
+
 pub fn cur_task() -> Option<Arc<ProcessControlBlock>> {
     PROCESSOR.borrow_mut().cur()
 }
@@ -65,7 +106,14 @@ pub fn cur_trap_cxt() -> &'static mut TrapContext {
 /// # processor::run
 /// start running processor
 /// > warn: endless loop
+///
+/// idle task runs on boot stack with interrupts disabled, since `__switch`
+/// must not be interrupted: `KERNEL_STACK_BOTTOM` and sp have to change together.
+/// When no task is ready, it waits for interrupts with `wfi`
 pub fn run() {
+    extern "C" {
+        fn stack0();
+    }
     loop {
         let mut processor = PROCESSOR.borrow_mut();
         if let Some(task) = fetch_task() {
@@ -73,7 +121,17 @@ pub fn run() {
             let mut task_inner = task.borrow_mut();
             let next_ptr = &task_inner.cxt as *const TaskContext;
             task_inner.status = TaskStatus::Running;
+            task_inner.restart_clock();
             drop(task_inner);
+            set_kernel_stack_bottom(task.kernel_stack.bottom());
+            RUNNING_PID.store(task.getpid(), Ordering::Relaxed);
+            // tickless: a task runs without time slice if no one else is ready
+            take_need_resched();
+            if has_ready_task() {
+                start_slice();
+            } else {
+                stop_slice();
+            }
             processor.cur = Some(task);
             drop(processor);
 
@@ -81,18 +139,44 @@ pub fn run() {
             unsafe {
                 __switch(idle_ptr, next_ptr);
             }
+            set_kernel_stack_bottom(stack0 as usize);
+            RUNNING_PID.store(NO_PID, Ordering::Relaxed);
+            stop_slice();
+            // nothing runs on kernel stack of exited task now
+            let exited = PROCESSOR.borrow_mut().exited.take();
+            if let Some(task) = exited {
+                bury(task);
+            }
+        } else {
+            drop(processor);
+            idle();
         }
     }
 }
 
+/// sleep until an interrupt comes, then handle it on boot stack.
+///
+/// `wfi` wakes up when an interrupt enabled in `sie` is pending even if
+/// `sstatus.SIE` is cleared, so no interrupt is lost between `fetch_task`
+/// and `wfi`.
+fn idle() {
+    unsafe {
+        wfi();
+    }
+    enable_kernel_interrupt();
+    disable_kernel_interrupt();
+}
+
 /// # processor::schedule
 /// change different task to run
 /// > warn: in detail, we will shift to *idle_task* first, and *idel_task* will switch to other available task
 pub fn schedule(cur_task: *mut TaskContext) {
+    let sie = disable_kernel_interrupt();
     let mut processor = PROCESSOR.borrow_mut();
     let idle_ptr = processor.idle_task_cxt_ptr();
     drop(processor);
     unsafe {
         __switch(cur_task, idle_ptr);
     }
+    restore_kernel_interrupt(sie);
 }
@@ -1,11 +1,15 @@
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 
 use crate::sync::UniProcSafeCell;
+use crate::task::processor::running_pid;
+use crate::timer::{add_deadline, ensure_slice, time};
 
 use super::task::ProcessControlBlock;
 
 pub struct ProcScheduler {
     rdy_que: VecDeque<Arc<ProcessControlBlock>>,
+    /// (deadline, task) of sleeping tasks, they are ready after deadline
+    sleepers: Vec<(usize, Arc<ProcessControlBlock>)>,
 }
 
 /// simple FIFO scheduler
@@ -13,6 +17,7 @@ impl ProcScheduler {
     pub fn new() -> Self {
         Self {
             rdy_que: VecDeque::new(),
+            sleepers: Vec::new(),
         }
     }
 
@@ -21,8 +26,38 @@ impl ProcScheduler {
     }
 
     pub fn fetch(&mut self) -> Option<Arc<ProcessControlBlock>> {
+        let now = time();
+        let rdy_que = &mut self.rdy_que;
+        self.sleepers.retain(|(deadline, task)| {
+            if *deadline <= now {
+                rdy_que.push_back(task.clone());
+            }
+            *deadline > now
+        });
         self.rdy_que.pop_front()
     }
+
+    pub fn sleep(&mut self, deadline: usize, task: Arc<ProcessControlBlock>) {
+        self.sleepers.push((deadline, task))
+    }
+
+    pub fn has_ready(&self) -> bool {
+        !self.rdy_que.is_empty()
+    }
+
+    /// the ready or sleeping task with the largest non-zero `key`
+    pub fn max_by_key(
+        &self,
+        key: impl Fn(&Arc<ProcessControlBlock>) -> usize,
+    ) -> Option<Arc<ProcessControlBlock>> {
+        self.rdy_que
+            .iter()
+            .chain(self.sleepers.iter().map(|(_, task)| task))
+            .map(|task| (key(task), task))
+            .filter(|&(k, _)| k > 0)
+            .max_by_key(|&(k, _)| k)
+            .map(|(_, task)| task.clone())
+    }
 }
 
 lazy_static! {
@@ -31,9 +66,24 @@ lazy_static! {
 }
 
 pub fn add_task(task: Arc<ProcessControlBlock>) {
-    PROC_SCHEDULER.borrow_mut().add(task)
+    PROC_SCHEDULER.borrow_mut().add(task);
+    // running task has to share processor now
+    if running_pid().is_some() {
+        ensure_slice();
+    }
 }
 
 pub fn fetch_task() -> Option<alloc::sync::Arc<ProcessControlBlock>> {
     PROC_SCHEDULER.borrow_mut().fetch()
 }
+
+/// `task` will be ready after `deadline`, in `time()`
+pub fn sleep_task(deadline: usize, task: Arc<ProcessControlBlock>) {
+    let pid = task.getpid();
+    PROC_SCHEDULER.borrow_mut().sleep(deadline, task);
+    add_deadline(deadline, pid);
+}
+
+pub fn has_ready_task() -> bool {
+    PROC_SCHEDULER.borrow_mut().has_ready()
+}
@@ -13,8 +13,10 @@ use crate::{
     mm::{
         address::{PhysPageNum, VirtAddr},
         memory_set::{MemorySet, KERNEL_SPACE},
+        MmResult,
     },
     sync::UniProcSafeCell,
+    timer::time_us,
     trap::{trap_handler, TrapContext},
 };
 
@@ -34,10 +36,29 @@ pub enum TaskStatus {
     Running,
     /// Task that already completed or killed
     Exited,
-    /// exited but not free memory yet
+    /// exited, it's freed after processor switches away from it
     Zombie,
 }
 
+/// resource usage of a process, kept in zombie until `waitpid`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Rusage {
+    /// time spent in user mode, in microseconds
+    pub utime_us: usize,
+    /// time spent in kernel mode, in microseconds
+    pub stime_us: usize,
+}
+
+/// # Zombie
+/// what's left of an exited child until parent `waitpid` it, its pid isn't
+/// reused before then
+pub struct Zombie {
+    pub pid: Pid,
+    pub exit_code: i32,
+    pub rusage: Rusage,
+}
+
 pub struct ProcessControlBlock {
     pub pid: Pid,
     pub kernel_stack: KernelStack,
@@ -52,8 +73,20 @@ pub struct ProcessControlBlockInner {
     pub base_size: usize,
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Arc<ProcessControlBlock>>,
+    /// children exited but not reaped
+    pub zombies: Vec<Zombie>,
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
     pub exit_code: i32,
+    /// log every syscall of this process, see `syscall::trace`
+    pub trace: bool,
+    pub rusage: Rusage,
+    /// `time_us()` when time was charged last time
+    clock_us: usize,
+    /// switched out in the middle of kernel work, e.g. sleeping in a syscall,
+    /// its kernel stack may own resources
+    pub in_kernel: bool,
+    /// killed by OOM killer, it exits before it returns to user
+    pub killed: bool,
 }
 
 impl ProcessControlBlockInner {
@@ -69,8 +102,20 @@ impl ProcessControlBlockInner {
         self.status
     }
 
-    pub fn is_zombie(&self) -> bool {
-        self.status == TaskStatus::Zombie
+    /// charge time since last charge to user or kernel mode
+    pub fn charge_time(&mut self, user: bool) {
+        let now = time_us();
+        if user {
+            self.rusage.utime_us += now - self.clock_us;
+        } else {
+            self.rusage.stime_us += now - self.clock_us;
+        }
+        self.clock_us = now;
+    }
+
+    /// time before now isn't spent by this process, e.g. when it's switched in
+    pub fn restart_clock(&mut self) {
+        self.clock_us = time_us();
     }
 
     pub fn alloc_fd(&mut self) -> usize {
@@ -86,7 +131,8 @@ impl ProcessControlBlockInner {
 impl ProcessControlBlock {
     pub fn new(elf_data: &[u8]) -> Self {
         debug!("create PCB for initproc");
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) =
+            MemorySet::from_elf(elf_data).expect("no memory for initproc");
         // green
         let trap_cxt_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
@@ -94,7 +140,7 @@ impl ProcessControlBlock {
             .ppn();
         let status = TaskStatus::Ready;
         let pid = pid_alloc();
-        let kernel_stack = KernelStack::new(pid.0);
+        let kernel_stack = KernelStack::new(pid.0).expect("no memory for initproc");
         let kernel_top = kernel_stack.top();
 
         let tcb = ProcessControlBlock {
@@ -108,7 +154,13 @@ impl ProcessControlBlock {
                 base_size: user_sp,
                 parent: None,
                 children: Vec::new(),
+                zombies: Vec::new(),
                 exit_code: 0,
+                trace: false,
+                rusage: Rusage::default(),
+                clock_us: 0,
+                in_kernel: false,
+                killed: false,
                 fd_table: vec![
                     // 0 -> stdin
                     Some(Arc::new(stdio::Stdin)),
@@ -140,15 +192,15 @@ impl ProcessControlBlock {
         self.pid.0
     }
 
-    pub fn fork(parent: &Arc<ProcessControlBlock>) -> Arc<ProcessControlBlock> {
+    pub fn fork(parent: &Arc<ProcessControlBlock>) -> MmResult<Arc<ProcessControlBlock>> {
         let mut parent_inner = parent.borrow_mut();
-        let memory_set = parent_inner.memory_set.clone();
+        let memory_set = parent_inner.memory_set.try_clone()?;
         let trap_cxt_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
         let pid = pid_alloc();
-        let kernel_stack = KernelStack::new(pid.0);
+        let kernel_stack = KernelStack::new(pid.0)?;
         let kernel_stack_top = kernel_stack.top();
 
         // copy fd table
@@ -172,8 +224,14 @@ impl ProcessControlBlock {
                 base_size: parent_inner.base_size,
                 parent: Some(Arc::downgrade(parent)),
                 children: Vec::new(),
+                zombies: Vec::new(),
                 fd_table: new_fd_table,
                 exit_code: 0,
+                trace: parent_inner.trace,
+                rusage: Rusage::default(),
+                clock_us: 0,
+                in_kernel: false,
+                killed: false,
             }),
         });
 
@@ -184,17 +242,19 @@ impl ProcessControlBlock {
         let trap_cxt = tcb.borrow_mut().trap_cxt();
         trap_cxt.kernel_sp = kernel_stack_top;
 
-        tcb
+        Ok(tcb)
     }
 
-    pub fn exec(&self, elf_data: &[u8]) {
-        let (memory_set, sp, entry) = MemorySet::from_elf(elf_data);
+    /// old address space is kept if it fails
+    pub fn exec(&self, elf_data: &[u8]) -> MmResult {
+        let (memory_set, sp, entry) = MemorySet::from_elf(elf_data)?;
         let trap_cxt_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
 
         let mut inner = self.borrow_mut();
+        inner.memory_set.sync_files();
         inner.memory_set = memory_set;
         inner.trap_cxt_ppn = trap_cxt_ppn;
 
@@ -205,6 +265,7 @@ impl ProcessControlBlock {
             KERNEL_SPACE.borrow_mut().token(),
             self.kernel_stack.top(),
             trap_handler as usize,
-        )
+        );
+        Ok(())
     }
 }
@@ -4,7 +4,7 @@ use crate::mm::memory_set::KERNEL_SPACE;
 use crate::test::{test_assert, test_assert_eq, test_fn};
 use alloc::vec::Vec;
 
-const MM_TEST_NUM: usize = 4;
+const MM_TEST_NUM: usize = 8;
 
 fn heap_test() {
     use alloc::boxed::Box;
@@ -39,6 +39,70 @@ fn heap_test2() {
     drop(v);
 }
 
+fn heap_grow_test() {
+    use crate::config::KERNEL_HEAP_SIZE;
+    use crate::mm::heap_allocator::heap_stats;
+    use alloc::vec;
+
+    let before = heap_stats();
+    // more than initial heap in total, taken 64 KiB each time
+    let chunks: Vec<Vec<u8>> = (0..KERNEL_HEAP_SIZE / 0x1_0000 + 1)
+        .map(|i| vec![i as u8; 0x1_0000])
+        .collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        test_assert(chunk.iter().all(|&b| b == i as u8));
+    }
+    let after = heap_stats();
+    test_assert(after.total > KERNEL_HEAP_SIZE);
+    test_assert_eq(after.total - before.total, after.grown - before.grown);
+    test_assert(after.grows > before.grows);
+    drop(chunks);
+    test_assert_eq(heap_stats().used, before.used);
+    test!("heap grow test...");
+}
+
+fn slab_test() {
+    use crate::mm::slab::{self, arc_layout, SlabCache};
+    use crate::task::task::ProcessControlBlock;
+    use core::alloc::Layout;
+
+    let cache = SlabCache::new("test", Layout::new::<[u64; 6]>());
+    let objs: Vec<_> = (0..100).map(|_| cache.alloc().unwrap()).collect();
+    let stats = cache.stats();
+    test_assert_eq(stats.active, 100);
+    test_assert(stats.slabs >= 2);
+    for (i, obj) in objs.iter().enumerate() {
+        unsafe { *(obj.as_ptr() as *mut [u64; 6]) = [i as u64; 6] };
+        test_assert(objs[..i].iter().all(|other| other != obj));
+    }
+    // objects don't overlap
+    for (i, obj) in objs.iter().enumerate() {
+        test_assert_eq(unsafe { *(obj.as_ptr() as *const [u64; 6]) }, [i as u64; 6]);
+    }
+    for obj in objs {
+        cache.dealloc(obj);
+    }
+    // last slab is kept
+    test_assert_eq(cache.stats().active, 0);
+    test_assert_eq(cache.stats().slabs, 1);
+
+    // allocation of process control block goes to its cache
+    let layout = arc_layout::<ProcessControlBlock>();
+    let active = |name| {
+        slab::slab_stats()
+            .iter()
+            .find(|stats| stats.name == name)
+            .unwrap()
+            .active
+    };
+    let before = active("process");
+    let ptr = unsafe { alloc::alloc::alloc(layout) };
+    test_assert_eq(active("process"), before + 1);
+    unsafe { alloc::alloc::dealloc(ptr, layout) };
+    test_assert_eq(active("process"), before);
+    test!("slab test...");
+}
+
 // import position of differnet sections
 use crate::config::edata;
 use crate::config::erodata;
@@ -91,12 +155,53 @@ pub fn frame_allocator_test() {
     drop(v);
     test!("frame allocator test...");
 }
+
+pub fn contiguous_frame_test() {
+    let before = frame_stats();
+    let a = frames_alloc_contiguous(3).unwrap();
+    let b = frames_alloc_contiguous(8).unwrap();
+    // 3 pages take a block of order 2
+    test_assert_eq(frame_stats().free, before.free - 4 - 8);
+    test_assert(a.0 + 4 <= b.0 || b.0 + 8 <= a.0);
+    frames_dealloc_contiguous(a, 3);
+    frames_dealloc_contiguous(b, 8);
+
+    // buddies are merged back
+    let after = frame_stats();
+    test_assert_eq(after.free, before.free);
+    test_assert_eq(after.free_blocks, before.free_blocks);
+    test!("contiguous frame test...");
+}
+
+pub fn huge_page_test() {
+    use crate::config::{ekernel, MEMORY_END, PAGE_SIZE};
+    use crate::mm::address::PhysPageNum;
+
+    let kernel_space = KERNEL_SPACE.borrow_mut();
+    let huge = 512 * PAGE_SIZE;
+    let mid = ((ekernel as usize + MEMORY_END) / 2) / huge * huge;
+    for va in [mid, mid + PAGE_SIZE * 3, MEMORY_END - PAGE_SIZE] {
+        let va: VirtAddr = va.into();
+        let pte = kernel_space.translate(va.floor()).unwrap();
+        test_assert(pte.is_valid() && pte.writable());
+        test_assert_eq(pte.ppn(), PhysPageNum(va.floor().0));
+    }
+    // direct map alone needs this many tables with 4 KiB pages
+    let tables_4k = (MEMORY_END - ekernel as usize) / PAGE_SIZE / 512;
+    test_assert(kernel_space.page_table().frame_count() < tables_4k);
+    test!("huge page test...");
+}
+
 pub fn mm_test() {
     test!("Memory Test Start: Running {} test\n", MM_TEST_NUM);
     test!("heap test1...");
     test_fn(heap_test);
     test!("heap test2...");
     test_fn(heap_test2);
+    test_fn(heap_grow_test);
+    test_fn(slab_test);
     test_fn(frame_allocator_test);
+    test_fn(contiguous_frame_test);
     test_fn(remap_test);
+    test_fn(huge_page_test);
 }
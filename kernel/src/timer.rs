@@ -1,10 +1,39 @@
-use crate::{config::CLOCK_FREQ, sbi::set_timer};
-use riscv::register;
+//! # Timer
+//!
+//! Tickless timer: instead of a fixed tick, we keep every deadline the kernel
+//! waits for and program `set_timer` only for the nearest one.
+//!
+//! - time slice: armed only when the running task has someone to share
+//!   processor with, see `processor::run`
+//! - sleeps and timeouts: deadlines in a min-heap, tasks waiting for them are
+//!   kept by scheduler, we only need to be interrupted on time
+//! - profiler sampling: periodic while a profile is running
+//!
+//! Deadlines are tagged with pid of the task waiting for them, and cancelled
+//! when it exits. A stale one would just cause a spurious reschedule.
+
+use crate::{
+    config::CLOCK_FREQ,
+    profile,
+    sbi::set_timer,
+    task::processor::{running_pid, set_need_resched},
+    trap::{
+        disable_kernel_interrupt, interrupt::register_interrupt_handler, restore_kernel_interrupt,
+    },
+};
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use riscv::register::{self, scause::Interrupt};
+use spin::Mutex;
 
-const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
 const MICRO_PER_SEC: usize = 1_000_000;
 
+/// length of time slice
+pub const SLICE_MS: usize = 10;
+/// period of profiler sampling
+const SAMPLE_MS: usize = 10;
+
 pub fn time() -> usize {
     register::time::read()
 }
@@ -17,6 +46,134 @@ pub fn time_us() -> usize {
     register::time::read() / (CLOCK_FREQ / MICRO_PER_SEC)
 }
 
-pub fn set_strigger() {
-    set_timer(time() + CLOCK_FREQ / TICKS_PER_SEC)
+/// `time()` after `ms` milliseconds
+pub fn deadline_after_ms(ms: usize) -> usize {
+    time() + ms * (CLOCK_FREQ / MSEC_PER_SEC)
+}
+
+struct Timers {
+    /// end of time slice of running task, `None` if it can run forever
+    slice: Option<usize>,
+    /// next sampling of profiler, `None` if no profile is running
+    sample: Option<usize>,
+    /// (deadline, pid) of sleeps and timeouts
+    deadlines: BinaryHeap<Reverse<(usize, usize)>>,
+}
+
+impl Timers {
+    /// program timer for the nearest deadline, or turn it off
+    fn program(&self) {
+        let next = [
+            self.slice,
+            self.sample,
+            self.deadlines.peek().map(|d| d.0 .0),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        // SBI has no way to cancel timer, a deadline far away does the same
+        set_timer(next.unwrap_or(usize::MAX));
+    }
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<Timers> = Mutex::new(Timers {
+        slice: None,
+        sample: None,
+        deadlines: BinaryHeap::new(),
+    });
+}
+
+/// run `f` with `TIMERS` locked and program timer after it,
+/// interrupts must be disabled while holding the lock
+fn with_timers<T>(f: impl FnOnce(&mut Timers) -> T) -> T {
+    let sie = disable_kernel_interrupt();
+    let mut timers = TIMERS.lock();
+    let res = f(&mut timers);
+    timers.program();
+    drop(timers);
+    restore_kernel_interrupt(sie);
+    res
+}
+
+/// give running task a new time slice
+pub fn start_slice() {
+    with_timers(|timers| timers.slice = Some(deadline_after_ms(SLICE_MS)));
+}
+
+/// running task can run until it gives up processor
+pub fn stop_slice() {
+    with_timers(|timers| timers.slice = None);
+}
+
+/// start a slice if running task has none, for a task just became ready
+pub fn ensure_slice() {
+    with_timers(|timers| {
+        if timers.slice.is_none() {
+            timers.slice = Some(deadline_after_ms(SLICE_MS));
+        }
+    });
+}
+
+/// interrupt processor at `deadline`, for task `pid`
+pub fn add_deadline(deadline: usize, pid: usize) {
+    with_timers(|timers| timers.deadlines.push(Reverse((deadline, pid))));
+}
+
+/// forget deadlines of task `pid`
+pub fn cancel_deadlines(pid: usize) {
+    with_timers(|timers| timers.deadlines.retain(|d| d.0 .1 != pid));
+}
+
+pub fn start_sampling() {
+    with_timers(|timers| {
+        if timers.sample.is_none() {
+            timers.sample = Some(deadline_after_ms(SAMPLE_MS));
+        }
+    });
+}
+
+pub fn stop_sampling() {
+    with_timers(|timers| timers.sample = None);
+}
+
+/// Handle every expired deadline. Heap only pops here, so nothing is allocated
+/// or freed in interrupt handler.
+///
+/// we can't switch task inside an interrupt handler, so just ask current task
+/// to yield before it goes back to user space
+fn timer_interrupt_handler() {
+    let now = time();
+    let mut resched = false;
+    let mut sample = false;
+
+    let mut timers = TIMERS.lock();
+    if timers.slice.map_or(false, |slice| slice <= now) {
+        timers.slice = None;
+        resched = true;
+    }
+    if timers.sample.map_or(false, |next| next <= now) {
+        timers.sample = Some(deadline_after_ms(SAMPLE_MS));
+        sample = true;
+    }
+    // someone sleeping wakes up, let scheduler find it
+    while timers.deadlines.peek().map_or(false, |d| d.0 .0 <= now) {
+        timers.deadlines.pop();
+        resched = true;
+    }
+    timers.program();
+    drop(timers);
+
+    if sample {
+        profile::sample();
+    }
+    // idle loop checks scheduler anyway after interrupt
+    if resched && running_pid().is_some() {
+        set_need_resched();
+    }
+}
+
+pub fn init() {
+    register_interrupt_handler(Interrupt::SupervisorTimer, timer_interrupt_handler);
+    with_timers(|_| {});
 }
@@ -37,3 +37,32 @@ impl TrapContext {
         ctx
     }
 }
+
+/// registers saved on current kernel stack by `__kerneltrap`
+#[repr(C)]
+pub struct KernelTrapContext {
+    pub x: [usize; 32],
+    pub sstatus: usize,
+    pub sepc: usize,
+}
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl KernelTrapContext {
+    /// print all registers, used before panic
+    pub fn dump(&self) {
+        println!("sepc: {:#018x} sstatus: {:#018x}", self.sepc, self.sstatus);
+        for (i, name) in REG_NAMES.iter().enumerate() {
+            print!("{:>4}: {:#018x}", name, self.x[i]);
+            if i % 4 == 3 {
+                println!();
+            } else {
+                print!("  ");
+            }
+        }
+    }
+}
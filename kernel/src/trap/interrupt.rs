@@ -0,0 +1,82 @@
+//! # Interrupt dispatch
+//!
+//! Drivers install handlers of supervisor interrupts here instead of editing
+//! the `match` in `trap_handler`. In kernel, `stvec` is in Vectored mode and
+//! each interrupt enters its own vector in `__kernelvec`, which calls its
+//! handler by `kernel_interrupt_handler` without looking at `scause`. Traps
+//! from user all enter trampoline, they look up the handler of an interrupt
+//! through `handle_interrupt`.
+
+use super::KernelTrapContext;
+use crate::sync::UniProcSafeCell;
+use riscv::register::{scause::Interrupt, sie};
+
+pub type InterruptHandler = fn();
+
+/// supervisor software, timer and external interrupt, vectors in
+/// `__kernelvec` pass their index
+const INTERRUPT_NUM: usize = 3;
+
+fn index(interrupt: Interrupt) -> Option<usize> {
+    match interrupt {
+        Interrupt::SupervisorSoft => Some(0),
+        Interrupt::SupervisorTimer => Some(1),
+        Interrupt::SupervisorExternal => Some(2),
+        _ => None,
+    }
+}
+
+lazy_static! {
+    static ref INTERRUPT_HANDLERS: UniProcSafeCell<[Option<InterruptHandler>; INTERRUPT_NUM]> =
+        UniProcSafeCell::new([None; INTERRUPT_NUM]);
+}
+
+/// install `handler` for `interrupt` and enable it in `sie`
+pub fn register_interrupt_handler(interrupt: Interrupt, handler: InterruptHandler) {
+    let idx = index(interrupt).expect("only supervisor interrupts can be registered");
+    let mut handlers = INTERRUPT_HANDLERS.borrow_mut();
+    assert!(
+        handlers[idx].is_none(),
+        "handler of {:?} has been registered",
+        interrupt
+    );
+    handlers[idx] = Some(handler);
+    drop(handlers);
+
+    unsafe {
+        match interrupt {
+            Interrupt::SupervisorSoft => sie::set_ssoft(),
+            Interrupt::SupervisorTimer => sie::set_stimer(),
+            Interrupt::SupervisorExternal => sie::set_sext(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// @return false if nobody handles `interrupt`
+pub fn handle_interrupt(interrupt: Interrupt) -> bool {
+    // don't hold the borrow while handler is running
+    let handler = index(interrupt).and_then(|idx| INTERRUPT_HANDLERS.borrow_mut()[idx]);
+    if let Some(handler) = handler {
+        handler();
+        true
+    } else {
+        false
+    }
+}
+
+/// # kernel_interrupt_handler
+/// entry of interrupt vectors in `__kernelvec`, `idx` is index of the
+/// interrupt in handler table
+#[no_mangle]
+pub extern "C" fn kernel_interrupt_handler(cxt: &mut KernelTrapContext, idx: usize) {
+    // don't hold the borrow while handler is running
+    let handler = INTERRUPT_HANDLERS.borrow_mut()[idx];
+    match handler {
+        Some(handler) => handler(),
+        None => {
+            cxt.dump();
+            panic!("no handler of interrupt {} in kernel", idx);
+        }
+    }
+}
@@ -1,33 +1,56 @@
 mod context;
+pub mod interrupt;
 
+use self::interrupt::handle_interrupt;
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::mm::memory_set::{MapPermission, StackFault};
+use crate::mm::uaccess::search_exception_table;
+use crate::mm::MmError;
 use crate::syscall::syscall;
-use crate::task::processor::{cur_trap_cxt, cur_user_token};
-use crate::task::{exit_cur_and_run_next, suspend_cur_and_run_next};
-use crate::timer::set_strigger;
-pub use context::TrapContext;
+use crate::task::coredump::{write_core, SIGILL, SIGSEGV};
+use crate::task::kernel_stack::{kernel_stack_guard_owner, kernel_stack_owner};
+use crate::task::processor::{cur_task, cur_trap_cxt, cur_user_token, take_need_resched};
+use crate::task::{exit_cur_and_run_next, preempt_cur_and_run_next};
+pub use context::{KernelTrapContext, TrapContext};
 use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::{
     mtvec::TrapMode,
-    scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    scause::{self, Exception, Trap},
+    sepc, sstatus, stval, stvec,
 };
 
+/// exit code of process killed by kernel
+pub const EXIT_PAGE_FAULT: i32 = -2;
+pub const EXIT_ILLEGAL_INSTRUCTION: i32 = -3;
+pub const EXIT_STACK_OVERFLOW: i32 = -5;
+pub const EXIT_OUT_OF_MEMORY: i32 = -6;
+
 /// include assembly code `trap.S` which do real work when trap happens
 global_asm!(include_str!("trap.S"));
 
+/// bottom of kernel stack in use, `__kerneltrap` compares sp with it to find
+/// out kernel stack overflow. 0 means unknown, and no check will be done.
+#[no_mangle]
+static KERNEL_STACK_BOTTOM: AtomicUsize = AtomicUsize::new(0);
+
+/// must be called with interrupts disabled, before sp moves to another stack
+pub fn set_kernel_stack_bottom(bottom: usize) {
+    KERNEL_STACK_BOTTOM.store(bottom, Ordering::Relaxed);
+}
+
 pub fn init() {
     set_kernel_trap_entry();
 }
 
 /// # trap_handler
 ///
-/// Address different trap, use match rather than trap vector
-///
-/// TODO: use trap vector
+/// Address different trap from user. Exceptions are handled by `match`,
+/// interrupts are dispatched to handlers registered in `interrupt`.
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    cur_task().unwrap().borrow_mut().charge_time(true);
     let mut cxt = cur_trap_cxt();
     let scause = scause::read();
     let stval = stval::read();
@@ -37,27 +60,53 @@ pub fn trap_handler() -> ! {
         // syscall interface
         Trap::Exception(Exception::UserEnvCall) => {
             cxt.sepc += 4;
-            let res = syscall(cxt.x[17], [cxt.x[10], cxt.x[11], cxt.x[12]]) as usize;
+            // syscall may take a long time, e.g. easy-fs operations,
+            // so we allow interrupts while handling it
+            enable_kernel_interrupt();
+            let args = [
+                cxt.x[10], cxt.x[11], cxt.x[12], cxt.x[13], cxt.x[14], cxt.x[15],
+            ];
+            let res = syscall(cxt.x[17], args) as usize;
+            disable_kernel_interrupt();
             // current context may be change by `exec`, so we have to get context again
             cxt = cur_trap_cxt();
             cxt.x[10] = res as usize;
         }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            let access = match scause.cause() {
+                Trap::Exception(Exception::StorePageFault) => MapPermission::W,
+                Trap::Exception(Exception::LoadPageFault) => MapPermission::R,
+                _ => MapPermission::X,
+            };
+            // pages of file mapping are read on demand
+            let fault = cur_task()
+                .unwrap()
+                .borrow_mut()
+                .memory_set
+                .handle_file_fault(stval.into(), access);
+            match fault {
+                Some(Ok(())) => {}
+                Some(Err(MmError::OutOfMemory)) => {
+                    error!("[kernel] No memory to map file for application, kernel will kill it");
+                    exit_cur_and_run_next(EXIT_OUT_OF_MEMORY);
+                }
+                Some(Err(_)) => kill_cur_task(scause.cause(), stval, SIGSEGV, EXIT_PAGE_FAULT),
+                None => handle_stack_fault(scause.cause(), stval),
+            }
+        }
         Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::LoadFault)
-        | Trap::Exception(Exception::LoadPageFault)
         | Trap::Exception(Exception::InstructionFault) => {
-            exit_cur_and_run_next(-2);
+            kill_cur_task(scause.cause(), stval, SIGSEGV, EXIT_PAGE_FAULT);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             error!("[kernel] Illegal Instruction in application, kernel will kill it");
-            exit_cur_and_run_next(-3);
+            kill_cur_task(scause.cause(), stval, SIGILL, EXIT_ILLEGAL_INSTRUCTION);
         }
 
-        Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_strigger();
-            suspend_cur_and_run_next();
-        }
+        Trap::Interrupt(interrupt) if handle_interrupt(interrupt) => {}
         _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}",
@@ -67,26 +116,162 @@ pub fn trap_handler() -> ! {
         }
     }
 
+    // time slice ran out, in user space or while we were in kernel
+    if take_need_resched() {
+        preempt_cur_and_run_next();
+    }
     trap_return();
 }
 
-pub fn enable_timer_interrupt() {
+/// user stack may grow on demand
+fn handle_stack_fault(cause: Trap, stval: usize) {
+    let fault = cur_task()
+        .unwrap()
+        .borrow_mut()
+        .memory_set
+        .handle_stack_fault(stval.into());
+    match fault {
+        StackFault::Grown => {}
+        StackFault::Overflow => {
+            error!("[kernel] Stack overflow in application, kernel will kill it");
+            kill_cur_task(cause, stval, SIGSEGV, EXIT_STACK_OVERFLOW);
+        }
+        StackFault::NotStack => kill_cur_task(cause, stval, SIGSEGV, EXIT_PAGE_FAULT),
+        StackFault::OutOfMemory => {
+            // no core dump, it needs memory too
+            error!("[kernel] No memory to grow stack of application, kernel will kill it");
+            exit_cur_and_run_next(EXIT_OUT_OF_MEMORY);
+        }
+    }
+}
+
+/// log the fault, write core dump of current task and kill it
+fn kill_cur_task(cause: Trap, stval: usize, signal: i32, exit_code: i32) {
+    let task = cur_task().unwrap();
+    let sepc = cur_trap_cxt().sepc;
+    error!(
+        "[kernel] {:?} in application pid = {}, sepc = {:#x}, stval = {:#x}",
+        cause,
+        task.getpid(),
+        sepc,
+        stval
+    );
+
+    // writing to easy-fs takes a long time
+    enable_kernel_interrupt();
+    write_core(&task, signal);
+    disable_kernel_interrupt();
+
+    drop(task);
+    exit_cur_and_run_next(exit_code);
+}
+
+/// allow interrupts to be taken in supervisor mode
+pub fn enable_kernel_interrupt() {
+    unsafe {
+        sstatus::set_sie();
+    }
+}
+
+/// @return whether interrupts were enabled before
+pub fn disable_kernel_interrupt() -> bool {
+    let sie = sstatus::read().sie();
     unsafe {
-        sie::set_stimer();
+        sstatus::clear_sie();
     }
+    sie
 }
 
+/// restore interrupt state returned by `disable_kernel_interrupt`
+pub fn restore_kernel_interrupt(sie: bool) {
+    if sie {
+        enable_kernel_interrupt();
+    }
+}
+
+/// traps from kernel use Vectored mode, interrupts with registered handler
+/// have their own vectors in `__kernelvec`
 pub fn set_kernel_trap_entry() {
+    extern "C" {
+        fn __kernelvec();
+    }
     unsafe {
-        stvec::write(trap_from_kernel as usize, TrapMode::Direct);
+        stvec::write(__kernelvec as usize, TrapMode::Vectored);
     }
 }
 
+/// # kernel_trap_handler
+///
+/// Address exceptions taken in supervisor mode, registers of interrupted code
+/// have been saved in `cxt` on current kernel stack by `__kerneltrap`.
+/// Interrupts enter their own vectors, see `interrupt`. Page faults of user
+/// memory access continue at their fixup, other exceptions are kernel bugs.
 #[no_mangle]
-pub fn trap_from_kernel() -> ! {
-    panic!("a trap from kernel")
+pub extern "C" fn kernel_trap_handler(cxt: &mut KernelTrapContext) {
+    let scause = scause::read();
+    let stval = stval::read();
+
+    match scause.cause() {
+        Trap::Exception(Exception::LoadPageFault) | Trap::Exception(Exception::StorePageFault)
+            if fixup_exception(cxt) => {}
+        _ => {
+            cxt.dump();
+            if let Some(pid) = kernel_stack_guard_owner(stval) {
+                panic!(
+                    "kernel stack overflow of pid {}, stval = {:#x}, sepc = {:#x}",
+                    pid, stval, cxt.sepc
+                );
+            }
+            panic!(
+                "a trap {:?} from kernel, stval = {:#x}, sepc = {:#x}",
+                scause.cause(),
+                stval,
+                cxt.sepc
+            )
+        }
+    }
 }
 
+/// continue at fixup of faulting instruction if it accesses user memory
+fn fixup_exception(cxt: &mut KernelTrapContext) -> bool {
+    match search_exception_table(cxt.sepc) {
+        Some(fixup) => {
+            cxt.sepc = fixup;
+            true
+        }
+        None => false,
+    }
+}
+
+/// # kernel_stack_overflow
+///
+/// Running on emergency stack, `kernel_sp` is the sp when trap happened
+#[no_mangle]
+pub extern "C" fn kernel_stack_overflow(kernel_sp: usize) -> ! {
+    let scause = scause::read();
+    let stval = stval::read();
+    let sepc = sepc::read();
+
+    if let Some(pid) = kernel_stack_owner(kernel_sp) {
+        panic!(
+            "kernel stack overflow of pid {}, trap {:?}, stval = {:#x}, sepc = {:#x}, sp = {:#x}",
+            pid,
+            scause.cause(),
+            stval,
+            sepc,
+            kernel_sp
+        );
+    }
+    panic!(
+        "boot stack overflow, trap {:?}, stval = {:#x}, sepc = {:#x}, sp = {:#x}",
+        scause.cause(),
+        stval,
+        sepc,
+        kernel_sp
+    )
+}
+
+/// traps from user all enter `__alltraps` to save `TrapContext`, so Direct mode is used
 fn set_user_trap_entry() {
     unsafe {
         stvec::write(TRAMPOLINE as usize, TrapMode::Direct);
@@ -96,9 +281,25 @@ fn set_user_trap_entry() {
 #[no_mangle]
 pub fn trap_return() -> ! {
     // debug!("trap return");
+    let killed = cur_task().unwrap().borrow_mut().killed;
+    if killed {
+        error!(
+            "[kernel] Out of memory, pid = {} is killed",
+            cur_task().unwrap().getpid()
+        );
+        exit_cur_and_run_next(EXIT_OUT_OF_MEMORY);
+    }
+    // stvec is going to point to trampoline, no interrupt is allowed in kernel from now on
+    disable_kernel_interrupt();
     set_user_trap_entry();
+    let task = cur_task().unwrap();
+    let mut inner = task.borrow_mut();
+    inner.charge_time(false);
+    // ASID of the address space may be taken by others since last switch
+    let user_satp = inner.memory_set.fresh_token();
+    drop(inner);
+    drop(task);
     let trap_cxt_ptr = TRAP_CONTEXT;
-    let user_satp = cur_user_token();
     extern "C" {
         fn __alltraps();
         fn __restore();
@@ -0,0 +1,30 @@
+//! # dmesg
+//!
+//! print kernel log buffer
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::syslog;
+
+/// same as `LOG_BUF_SIZE` in kernel
+const LOG_BUF_SIZE: usize = 4096 * 4;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // kernel grows user stack to hold it
+    let mut buf = [0u8; LOG_BUF_SIZE];
+    let len = syslog(&mut buf);
+    if len < 0 {
+        println!("failed to read kernel log");
+        return -1;
+    }
+    match core::str::from_utf8(&buf[..len as usize]) {
+        Ok(log) => print!("{}", log),
+        Err(_) => println!("kernel log is broken"),
+    }
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::slice;
+use user_lib::{exit, fork, open, read, waitpid, write, OpenFlags, EFAULT};
+
+/// below where program is loaded, never mapped
+const UNMAPPED: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let bad = unsafe { slice::from_raw_parts(UNMAPPED as *const u8, 16) };
+    assert_eq!(write(1, bad), -EFAULT);
+    // buffer runs from a mapped page into kernel half
+    let huge = unsafe { slice::from_raw_parts(main as usize as *const u8, usize::MAX / 2) };
+    assert_eq!(write(1, huge), -EFAULT);
+    println!("bad source: ok");
+
+    // text is read only
+    let text = unsafe { slice::from_raw_parts_mut(main as usize as *mut u8, 16) };
+    assert_eq!(read(0, text), -EFAULT);
+    println!("read-only destination: ok");
+
+    let path = unsafe { core::str::from_utf8_unchecked(bad) };
+    assert_eq!(open(path, OpenFlags::RDONLY), -EFAULT);
+    println!("bad path: ok");
+
+    // child is reaped only after its exit code is copied out
+    let pid = fork();
+    if pid == 0 {
+        exit(7);
+    }
+    let text_code = unsafe { &mut *(main as usize as *mut i32) };
+    assert_eq!(waitpid(pid as usize, text_code), -EFAULT);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 7);
+    println!("bad exit code: ok");
+
+    println!("efault_test passed!");
+    0
+}
@@ -0,0 +1,46 @@
+//! # loglevel
+//!
+//! change log level of kernel, input like `debug` or `trace mm::memory_set`
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{log_level, readline, LOG_DEBUG, LOG_ERROR, LOG_INFO, LOG_OFF, LOG_TRACE, LOG_WARN};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    print!("level(off/error/warn/info/debug/trace) [module]: ");
+    let line = readline();
+    let mut args = line.trim_end_matches('\0').split_whitespace();
+
+    let level = match args.next() {
+        Some("off") => LOG_OFF,
+        Some("error") => LOG_ERROR,
+        Some("warn") => LOG_WARN,
+        Some("info") => LOG_INFO,
+        Some("debug") => LOG_DEBUG,
+        Some("trace") => LOG_TRACE,
+        _ => {
+            println!("unknown log level");
+            return -1;
+        }
+    };
+
+    let res = if let Some(module) = args.next() {
+        let mut module = String::from(module);
+        module.push('\0');
+        log_level(level, Some(module.as_str()))
+    } else {
+        log_level(level, None)
+    };
+    if res < 0 {
+        println!("failed to set log level");
+        return -1;
+    }
+    0
+}
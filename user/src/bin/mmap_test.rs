@@ -0,0 +1,155 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::{read_volatile, write_volatile};
+use user_lib::{
+    close, exit, fork, mmap, mprotect, msync, munmap, open, read, waitpid, write, OpenFlags,
+    EACCES, EINVAL, EXIT_PAGE_FAULT, MAP_PRIVATE, MAP_SHARED, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+const FILE: &str = "mmapfile\0";
+/// two pages and a bit of the third one
+const FILE_SIZE: usize = PAGE_SIZE * 2 + 100;
+
+fn pattern(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+fn read_file(buf: &mut [u8]) -> &[u8] {
+    let fd = open(FILE, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, buf) as usize;
+    close(fd as usize);
+    &buf[..len]
+}
+
+fn at(base: isize, offset: usize) -> *mut u8 {
+    (base as usize + offset) as *mut u8
+}
+
+/// run `f` in child, @return its exit code
+fn in_child(f: impl FnOnce()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // buffer spans more than initial stack, kernel grows stack to reach it
+    let mut buf = [0u8; FILE_SIZE];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = pattern(i);
+    }
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &buf), FILE_SIZE as isize);
+
+    assert_eq!(mmap(PAGE_SIZE, PROT_READ, MAP_SHARED, fd, 1), -EINVAL);
+    assert_eq!(mmap(PAGE_SIZE, PROT_READ, 0, fd, 0), -EINVAL);
+
+    // pages are read from file on first access, the last one is beyond end of file
+    let shared = mmap(PAGE_SIZE * 4, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    assert!(shared > 0, "mmap failed: {}", shared);
+    for i in 0..FILE_SIZE {
+        assert_eq!(unsafe { read_volatile(at(shared, i)) }, pattern(i));
+    }
+    println!("read by fault: ok");
+
+    unsafe {
+        write_volatile(at(shared, 0), 0xaa);
+        write_volatile(at(shared, PAGE_SIZE + 5), 0xbb);
+    }
+    assert_eq!(msync(shared as usize, FILE_SIZE), 0);
+    let data = read_file(&mut buf);
+    assert_eq!(data.len(), FILE_SIZE);
+    assert_eq!((data[0], data[PAGE_SIZE + 5]), (0xaa, 0xbb));
+    println!("msync of shared mapping: ok");
+
+    // private mapping copies page on write, file and other mappings keep old data
+    let private = mmap(PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+    assert!(private > 0, "mmap failed: {}", private);
+    assert_eq!(unsafe { read_volatile(at(private, 0)) }, 0xaa);
+    unsafe { write_volatile(at(private, 0), 0xcc) };
+    assert_eq!(unsafe { read_volatile(at(shared, 0)) }, 0xaa);
+    assert_eq!(munmap(private as usize, PAGE_SIZE), 0);
+    assert_eq!(read_file(&mut buf)[0], 0xaa);
+    println!("copy on write of private mapping: ok");
+
+    // mapping is shared with child, its writes are written back at exit
+    let exit_code = in_child(|| unsafe { write_volatile(at(shared, 7), 0xdd) });
+    assert_eq!(exit_code, 0);
+    assert_eq!(unsafe { read_volatile(at(shared, 7)) }, 0xdd);
+    assert_eq!(read_file(&mut buf)[7], 0xdd);
+    println!("write back at exit: ok");
+
+    // a sync through one mapping doesn't hide later writes through another
+    unsafe { write_volatile(at(shared, 9), 0x11) };
+    let exit_code = in_child(|| unsafe { write_volatile(at(shared, 10), 0x22) });
+    assert_eq!(exit_code, 0);
+    unsafe { write_volatile(at(shared, 11), 0x33) };
+    assert_eq!(msync(shared as usize, PAGE_SIZE), 0);
+    let data = read_file(&mut buf);
+    assert_eq!((data[9], data[10], data[11]), (0x11, 0x22, 0x33));
+    println!("dirty page of each mapping: ok");
+
+    // `write` reaches mapped page, and msync doesn't write old data over it
+    unsafe { write_volatile(at(shared, 20), 0x55) };
+    let wfd = open(FILE, OpenFlags::RDWR);
+    assert!(wfd > 0);
+    assert_eq!(write(wfd as usize, &[0x66; 4]), 4);
+    close(wfd as usize);
+    assert_eq!(unsafe { read_volatile(at(shared, 0)) }, 0x66);
+    assert_eq!(read_file(&mut buf)[20], 0x55);
+    assert_eq!(msync(shared as usize, PAGE_SIZE), 0);
+    let data = read_file(&mut buf);
+    assert_eq!((data[0], data[3], data[20]), (0x66, 0x66, 0x55));
+    println!("write to mapped file: ok");
+
+    // page wholly beyond end of file can't be accessed
+    let exit_code = in_child(|| unsafe {
+        read_volatile(at(shared, PAGE_SIZE * 3));
+    });
+    assert_eq!(exit_code, EXIT_PAGE_FAULT);
+
+    unsafe { write_volatile(at(shared, 8), 0xee) };
+    assert_eq!(munmap(shared as usize, PAGE_SIZE * 4), 0);
+    assert_eq!(read_file(&mut buf)[8], 0xee);
+    let exit_code = in_child(|| unsafe {
+        read_volatile(at(shared, 0));
+    });
+    assert_eq!(exit_code, EXIT_PAGE_FAULT);
+    println!("munmap: ok");
+
+    // file opened read only can't be written through shared mapping
+    let ro_fd = open(FILE, OpenFlags::RDONLY);
+    assert!(ro_fd > 0);
+    let ro_fd = ro_fd as usize;
+    assert_eq!(
+        mmap(PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, ro_fd, 0),
+        -EACCES
+    );
+    let ro = mmap(PAGE_SIZE, PROT_READ, MAP_SHARED, ro_fd, 0);
+    assert!(ro > 0, "mmap failed: {}", ro);
+    assert_eq!(
+        mprotect(ro as usize, PAGE_SIZE, PROT_READ | PROT_WRITE),
+        -EACCES
+    );
+    assert_eq!(munmap(ro as usize, PAGE_SIZE), 0);
+    close(ro_fd);
+    println!("read only file: ok");
+
+    close(fd);
+    println!("mmap_test passed!");
+    0
+}
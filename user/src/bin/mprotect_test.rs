@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+use user_lib::{
+    fork, mprotect, waitpid, EINVAL, ENOMEM, EXIT_PAGE_FAULT, PROT_NONE, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+#[repr(C, align(4096))]
+struct Pages([u8; PAGE_SIZE * 3]);
+
+static mut PAGES: Pages = Pages([0; PAGE_SIZE * 3]);
+
+fn page(idx: usize) -> *mut u8 {
+    unsafe { (addr_of_mut!(PAGES) as *mut u8).add(idx * PAGE_SIZE) }
+}
+
+/// run `f` in child, @return its exit code
+fn in_child(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        user_lib::exit(0);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for idx in 0..3 {
+        unsafe { write_volatile(page(idx), idx as u8 + 1) };
+    }
+
+    assert_eq!(
+        mprotect(page(1) as usize + 1, PAGE_SIZE, PROT_READ),
+        -EINVAL
+    );
+    assert_eq!(mprotect(0x20_0000_0000, PAGE_SIZE, PROT_READ), -ENOMEM);
+
+    // middle page is split out of data area
+    assert_eq!(mprotect(page(1) as usize, PAGE_SIZE, PROT_READ), 0);
+    assert_eq!(unsafe { read_volatile(page(1)) }, 2);
+    assert_eq!(
+        in_child(|| unsafe { write_volatile(page(1), 0) }),
+        EXIT_PAGE_FAULT
+    );
+    // pages around it are still writable
+    unsafe {
+        write_volatile(page(0), 10);
+        write_volatile(page(2), 30);
+    }
+    println!("read-only page: ok");
+
+    assert_eq!(mprotect(page(1) as usize, PAGE_SIZE, PROT_NONE), 0);
+    assert_eq!(
+        in_child(|| unsafe {
+            read_volatile(page(1));
+        }),
+        EXIT_PAGE_FAULT
+    );
+    println!("guard page: ok");
+
+    assert_eq!(
+        mprotect(page(0) as usize, PAGE_SIZE * 3, PROT_READ | PROT_WRITE),
+        0
+    );
+    unsafe { write_volatile(page(1), 20) };
+    assert_eq!(unsafe { read_volatile(page(1)) }, 20);
+    println!("writable again: ok");
+
+    // nothing is changed for empty range
+    assert_eq!(mprotect(page(1) as usize, 0, PROT_NONE), 0);
+    unsafe { write_volatile(page(1), 21) };
+    assert_eq!(unsafe { read_volatile(page(1)) }, 21);
+    println!("empty range: ok");
+
+    println!("mprotect_test passed!");
+    0
+}
@@ -0,0 +1,106 @@
+//! # profile
+//!
+//! run a program under the sampling profiler, and show where it spends time.
+//! Kernel samples are grouped by kernel symbol, user samples by pc.
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{
+    exec, fork, ksym, profile_read, profile_start, profile_stop, readline, waitpid_rusage, Rusage,
+    Sample, PROFILE_KERNEL,
+};
+
+const MAX_SAMPLES: usize = 256;
+const TOP: usize = 20;
+
+// too large for user heap
+static mut SAMPLES: [Sample; MAX_SAMPLES] = [Sample {
+    pc: 0,
+    count: 0,
+    mode: 0,
+}; MAX_SAMPLES];
+
+fn kernel_symbol(pc: usize) -> String {
+    let mut name = [0u8; 128];
+    match ksym(pc, &mut name) {
+        len if len > 0 => String::from_utf8_lossy(&name[..len as usize]).into_owned(),
+        _ => alloc::format!("{:#x}", pc),
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    print!("program: ");
+    let prog = readline();
+
+    let pid = fork();
+    if pid == 0 {
+        // child process, profile is kept across exec
+        if profile_start(-1) == -1 {
+            println!("Too many tasks are being profiled");
+            return -1;
+        }
+        if exec(prog.as_str()) == -1 {
+            profile_stop(-1, true);
+            println!("Error when executing...");
+            return -4;
+        }
+        unreachable!();
+    }
+
+    let mut exit_code = 0;
+    let mut rusage = Rusage::default();
+    waitpid_rusage(pid as usize, &mut exit_code, &mut rusage);
+    let samples = unsafe { &mut *core::ptr::addr_of_mut!(SAMPLES) };
+    let len = profile_read(pid, samples);
+    profile_stop(pid, true);
+    if len <= 0 {
+        println!("profile: no samples of process {}", pid);
+        return -1;
+    }
+
+    // (where, count), kernel samples in one function are merged
+    let mut hits: Vec<(String, u32)> = Vec::new();
+    let mut total = 0;
+    for sample in &samples[..len as usize] {
+        total += sample.count;
+        let place = if sample.mode == PROFILE_KERNEL {
+            alloc::format!("[kernel] {}", kernel_symbol(sample.pc))
+        } else {
+            alloc::format!("[user]   {:#x}", sample.pc)
+        };
+        match hits.iter_mut().find(|(p, _)| *p == place) {
+            Some((_, count)) => *count += sample.count,
+            None => hits.push((place, sample.count)),
+        }
+    }
+    hits.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+
+    println!(
+        "profile: process {} exit with code {}, {} samples, user {}ms, kernel {}ms",
+        pid,
+        exit_code,
+        total,
+        rusage.utime_us / 1000,
+        rusage.stime_us / 1000
+    );
+    for (place, count) in hits.iter().take(TOP) {
+        // no float here, in case kernel doesn't save float registers
+        let permille = *count as usize * 1000 / total as usize;
+        println!(
+            "{:>6} {:>3}.{}% {}",
+            count,
+            permille / 10,
+            permille % 10,
+            place
+        );
+    }
+    0
+}
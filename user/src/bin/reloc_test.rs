@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+// pointers in data of PIE are only filled by relocation, which must be done
+// even if it's loaded where it's linked, e.g. with `ASLR=off`
+
+fn one() -> usize {
+    1
+}
+
+fn two() -> usize {
+    2
+}
+
+static GREETING: &str = "relocated";
+static FNS: [fn() -> usize; 2] = [one, two];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let greeting = unsafe { core::ptr::read_volatile(&GREETING) };
+    assert_eq!(greeting, "relocated");
+    println!("string in data: ok");
+
+    let fns = unsafe { core::ptr::read_volatile(&FNS) };
+    assert_eq!(fns[0]() + fns[1](), 3);
+    println!("function pointers in data: ok");
+
+    println!("reloc_test passed!");
+    0
+}
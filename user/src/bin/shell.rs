@@ -13,7 +13,7 @@ extern crate alloc;
 
 use user_lib::console::{println_with_color, BLUE};
 use user_lib::osh::Command;
-use user_lib::{exec, fork, osh, waitpid};
+use user_lib::{exec, fork, osh, waitpid, ENOMEM, EXIT_OUT_OF_MEMORY, EXIT_STACK_OVERFLOW};
 
 #[no_mangle]
 fn main() -> i32 {
@@ -25,17 +25,31 @@ fn main() -> i32 {
             match command {
                 Command::Bin(bin) => {
                     let pid = fork();
-                    if pid == 0 {
+                    if pid == -ENOMEM {
+                        println!("Shell: out of memory, can't fork");
+                    } else if pid == 0 {
                         // child process
-                        if exec(bin.as_str()) == -1 {
-                            println!("Error when executing...");
-                            return -4;
+                        match exec(bin.as_str()) {
+                            -1 => {
+                                println!("Error when executing...");
+                                return -4;
+                            }
+                            res if res == -ENOMEM => {
+                                println!("Shell: out of memory, can't execute");
+                                return -4;
+                            }
+                            _ => {}
                         }
                     } else {
                         // father process
                         let mut exit_code = 0;
                         let exit_pid = waitpid(pid as usize, &mut exit_code);
                         assert_eq!(exit_pid, pid);
+                        if exit_code == EXIT_STACK_OVERFLOW {
+                            println!("Shell: Process {} killed by stack overflow", pid);
+                        } else if exit_code == EXIT_OUT_OF_MEMORY {
+                            println!("Shell: Process {} killed for out of memory", pid);
+                        }
                         println!("Shell: Process {} exit with code {}", pid, exit_code);
                     }
                 }
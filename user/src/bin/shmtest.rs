@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{fork, shm_attach, shm_create, shm_detach, user_yield, waitpid, EEXIST};
+
+const KEY: usize = 0x5348_4d00;
+const SLOTS: usize = 1024;
+const COUNT: usize = 100_000;
+
+/// single producer single consumer ring living in shared memory
+#[repr(C)]
+struct Ring {
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    slots: [usize; SLOTS],
+}
+
+fn producer(ring: &mut Ring) {
+    for i in 0..COUNT {
+        let tail = ring.tail.load(Ordering::Relaxed);
+        while tail - ring.head.load(Ordering::Acquire) == SLOTS {
+            user_yield();
+        }
+        ring.slots[tail % SLOTS] = i;
+        ring.tail.store(tail + 1, Ordering::Release);
+    }
+}
+
+fn consumer(ring: &mut Ring) -> usize {
+    let mut sum = 0;
+    for _ in 0..COUNT {
+        let head = ring.head.load(Ordering::Relaxed);
+        while ring.tail.load(Ordering::Acquire) == head {
+            user_yield();
+        }
+        sum += ring.slots[head % SLOTS];
+        ring.head.store(head + 1, Ordering::Release);
+    }
+    sum
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let addr = shm_create(KEY, core::mem::size_of::<Ring>());
+    if addr == -EEXIST {
+        println!("shmtest: segment {:#x} is in use", KEY);
+        return -1;
+    }
+    assert!(addr > 0, "shm_create failed: {}", addr);
+    assert_eq!(shm_create(KEY, 1), -EEXIST);
+
+    let pid = fork();
+    if pid == 0 {
+        // the mapping is inherited, attach again by key to share it as an unrelated process
+        assert_eq!(shm_detach(addr as usize), 0);
+        let addr = shm_attach(KEY);
+        assert!(addr > 0, "shm_attach failed: {}", addr);
+        let ring = unsafe { &mut *(addr as *mut Ring) };
+        let sum = consumer(ring);
+        assert_eq!(shm_detach(addr as usize), 0);
+        return if sum == COUNT * (COUNT - 1) / 2 { 0 } else { 1 };
+    }
+
+    let ring = unsafe { &mut *(addr as *mut Ring) };
+    producer(ring);
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    assert_eq!(shm_detach(addr as usize), 0);
+    assert!(
+        shm_attach(KEY) < 0,
+        "segment should be freed after last detach"
+    );
+    if exit_code == 0 {
+        println!("shmtest passed: {} values through shared memory", COUNT);
+        0
+    } else {
+        println!("shmtest failed: consumer got wrong sum");
+        1
+    }
+}
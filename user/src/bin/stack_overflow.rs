@@ -11,7 +11,7 @@ fn f(d: usize) {
 
 #[no_mangle]
 pub fn main() -> i32 {
-    println!("It should trigger segmentation fault!");
+    println!("It should trigger stack overflow!");
     f(0);
     0
 }
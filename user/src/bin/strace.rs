@@ -0,0 +1,34 @@
+//! # strace
+//!
+//! run a program with its syscalls logged by kernel
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, fork, readline, trace, waitpid};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    print!("program: ");
+    let prog = readline();
+
+    let pid = fork();
+    if pid == 0 {
+        // child process, tracing is kept across exec
+        trace(true);
+        if exec(prog.as_str()) == -1 {
+            trace(false);
+            println!("Error when executing...");
+            return -4;
+        }
+        unreachable!();
+    }
+
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    println!("strace: process {} exit with code {}", pid, exit_code);
+    0
+}
@@ -21,6 +21,7 @@ static TESTS: &[&str] = &[
     "filetest_simple\0",
     "cat_filea\0",
     "huge_write\0",
+    "reloc_test\0",
 ];
 
 use alloc::string::ToString;
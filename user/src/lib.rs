@@ -91,7 +91,7 @@ pub fn close(fd: usize) -> isize {
 
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
+        match sys_waitpid(-1, exit_code as *mut _, core::ptr::null_mut()) {
             -2 => user_yield(),
             exit_pid => return exit_pid,
         };
@@ -100,7 +100,27 @@ pub fn wait(exit_code: &mut i32) -> isize {
 
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
+        match sys_waitpid(pid as isize, exit_code as *mut _, core::ptr::null_mut()) {
+            -2 => user_yield(),
+            exit_pid => return exit_pid,
+        };
+    }
+}
+
+/// resource usage of an exited process, same as `Rusage` in kernel
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Rusage {
+    /// time spent in user mode, in microseconds
+    pub utime_us: usize,
+    /// time spent in kernel mode, in microseconds
+    pub stime_us: usize,
+}
+
+/// same as `waitpid`, and get resource usage of the child
+pub fn waitpid_rusage(pid: usize, exit_code: &mut i32, rusage: &mut Rusage) -> isize {
+    loop {
+        match sys_waitpid(pid as isize, exit_code as *mut _, rusage as *mut _) {
             -2 => user_yield(),
             exit_pid => return exit_pid,
         };
@@ -114,6 +134,124 @@ pub fn sleep(time_ms: usize) {
     }
 }
 
+/// exit code of process killed by kernel
+pub const EXIT_PAGE_FAULT: i32 = -2;
+pub const EXIT_ILLEGAL_INSTRUCTION: i32 = -3;
+pub const EXIT_STACK_OVERFLOW: i32 = -5;
+pub const EXIT_OUT_OF_MEMORY: i32 = -6;
+
+/// error numbers returned by syscalls as negative values, same as Linux
+pub const ENOENT: isize = 2;
+pub const EBADF: isize = 9;
+pub const ENOMEM: isize = 12;
+pub const EACCES: isize = 13;
+pub const EFAULT: isize = 14;
+pub const EEXIST: isize = 17;
+pub const ENODEV: isize = 19;
+pub const EINVAL: isize = 22;
+
+/// kernel log level
+pub const LOG_OFF: usize = 0;
+pub const LOG_ERROR: usize = 1;
+pub const LOG_WARN: usize = 2;
+pub const LOG_INFO: usize = 3;
+pub const LOG_DEBUG: usize = 4;
+pub const LOG_TRACE: usize = 5;
+
+/// set log level of kernel module like `mm::memory_set\0`, or the whole kernel if `None`
+pub fn log_level(level: usize, module: Option<&str>) -> isize {
+    sys_log_level(level, module.map_or(core::ptr::null(), |m| m.as_ptr()))
+}
+
+/// read kernel log buffer, only the latest records are read if `buffer` is small
+pub fn syslog(buffer: &mut [u8]) -> isize {
+    sys_syslog(buffer)
+}
+
+/// log every syscall of current process and its children forked later
+pub fn trace(enable: bool) -> isize {
+    sys_trace(enable as usize)
+}
+
+/// sample of profiler, same as `profile::Sample` in kernel
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Sample {
+    pub pc: usize,
+    pub count: u32,
+    pub mode: u32,
+}
+
+pub const PROFILE_USER: u32 = 0;
+pub const PROFILE_KERNEL: u32 = 1;
+
+/// profile task `pid`, -1 for current task
+pub fn profile_start(pid: isize) -> isize {
+    sys_profile_start(pid)
+}
+
+/// stop profiling `pid`, its samples are released if `release`
+pub fn profile_stop(pid: isize, release: bool) -> isize {
+    sys_profile_stop(pid, release as usize)
+}
+
+/// read samples of `pid`, most frequent first
+pub fn profile_read(pid: isize, samples: &mut [Sample]) -> isize {
+    sys_profile_read(pid, samples)
+}
+
+/// name of kernel symbol containing `addr`
+pub fn ksym(addr: usize, buffer: &mut [u8]) -> isize {
+    sys_ksym(addr, buffer)
+}
+
+/// page protection for `mprotect`, writable pages are readable too
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+
+/// change protection of pages in `[addr, addr + len)`, `addr` must be page aligned
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(addr, len, prot)
+}
+
+/// flags of `mmap`, exactly one of them must be given
+pub const MAP_SHARED: usize = 1 << 0;
+pub const MAP_PRIVATE: usize = 1 << 1;
+
+/// map `len` bytes of file `fd` from `offset`, which must be page aligned,
+/// @return address of mapping, or negative errno
+pub fn mmap(len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    sys_mmap(len, prot, flags, fd, offset)
+}
+
+/// unmap file mappings in `[addr, addr + len)`, shared mappings are written back first
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+
+/// write changes of shared file mappings in `[addr, addr + len)` to file
+pub fn msync(addr: usize, len: usize) -> isize {
+    sys_msync(addr, len)
+}
+
+/// create shared memory of `size` bytes for `key` and attach it,
+/// @return its address, or negative errno
+pub fn shm_create(key: usize, size: usize) -> isize {
+    sys_shm_create(key, size)
+}
+
+/// attach shared memory of `key`, it's kept alive by processes attaching it
+pub fn shm_attach(key: usize) -> isize {
+    sys_shm_attach(key)
+}
+
+/// detach shared memory at `addr`, children forked before keep their mapping
+pub fn shm_detach(addr: usize) -> isize {
+    sys_shm_detach(addr)
+}
+
 pub fn readline() -> String {
     console::getline()
 }
@@ -28,6 +28,15 @@ static BUILTIN_BIN: &[&str] = &[
     "cat_filea\0",
     "filetest_simple\0",
     "shutdown\0",
+    "loglevel\0",
+    "dmesg\0",
+    "strace\0",
+    "profile\0",
+    "shmtest\0",
+    "mprotect_test\0",
+    "mmap_test\0",
+    "efault_test\0",
+    "reloc_test\0",
 ];
 
 // use crate::console::BS;
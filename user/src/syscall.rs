@@ -1,3 +1,4 @@
+use crate::{Rusage, Sample};
 use core::arch::asm;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
@@ -14,6 +15,24 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+/// syscall with six arguments, e.g. `mmap`
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => ret,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a7") id
+        );
+    }
+    ret
+}
+
 /// syscall numbers
 const SYSCALL_SHUTDOWN: usize = 48;
 const SYSCALL_OPEN: usize = 56;
@@ -21,12 +40,26 @@ const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_SYSLOG: usize = 116;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_FORK: usize = 220;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_LOG_LEVEL: usize = 401;
+const SYSCALL_TRACE: usize = 402;
+const SYSCALL_PROFILE_START: usize = 403;
+const SYSCALL_PROFILE_STOP: usize = 404;
+const SYSCALL_PROFILE_READ: usize = 405;
+const SYSCALL_KSYM: usize = 406;
+const SYSCALL_SHM_CREATE: usize = 407;
+const SYSCALL_SHM_ATTACH: usize = 408;
+const SYSCALL_SHM_DETACH: usize = 409;
 
 /// syscall implementation
 
@@ -77,6 +110,74 @@ pub fn sys_exec(path: &str) -> isize {
     syscall(SYSCALL_EXEC, [path.as_ptr() as usize, 0, 0])
 }
 
-pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
+pub fn sys_waitpid(pid: isize, exit_code: *mut i32, rusage: *mut Rusage) -> isize {
+    syscall(
+        SYSCALL_WAITPID,
+        [pid as usize, exit_code as usize, rusage as usize],
+    )
+}
+
+pub fn sys_log_level(level: usize, module: *const u8) -> isize {
+    syscall(SYSCALL_LOG_LEVEL, [level, module as usize, 0])
+}
+
+pub fn sys_syslog(buffer: &mut [u8]) -> isize {
+    syscall(
+        SYSCALL_SYSLOG,
+        [buffer.as_mut_ptr() as usize, buffer.len(), 0],
+    )
+}
+
+pub fn sys_trace(enable: usize) -> isize {
+    syscall(SYSCALL_TRACE, [enable, 0, 0])
+}
+
+pub fn sys_profile_start(pid: isize) -> isize {
+    syscall(SYSCALL_PROFILE_START, [pid as usize, 0, 0])
+}
+
+pub fn sys_profile_stop(pid: isize, release: usize) -> isize {
+    syscall(SYSCALL_PROFILE_STOP, [pid as usize, release, 0])
+}
+
+pub fn sys_profile_read(pid: isize, samples: &mut [Sample]) -> isize {
+    syscall(
+        SYSCALL_PROFILE_READ,
+        [pid as usize, samples.as_mut_ptr() as usize, samples.len()],
+    )
+}
+
+pub fn sys_ksym(addr: usize, buffer: &mut [u8]) -> isize {
+    syscall(
+        SYSCALL_KSYM,
+        [addr, buffer.as_mut_ptr() as usize, buffer.len()],
+    )
+}
+
+pub fn sys_shm_create(key: usize, size: usize) -> isize {
+    syscall(SYSCALL_SHM_CREATE, [key, size, 0])
+}
+
+pub fn sys_shm_attach(key: usize) -> isize {
+    syscall(SYSCALL_SHM_ATTACH, [key, 0, 0])
+}
+
+pub fn sys_shm_detach(addr: usize) -> isize {
+    syscall(SYSCALL_SHM_DETACH, [addr, 0, 0])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [addr, len, prot])
+}
+
+pub fn sys_mmap(len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    syscall6(SYSCALL_MMAP, [0, len, prot, flags, fd, offset])
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_msync(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MSYNC, [addr, len, 0])
 }